@@ -0,0 +1,3 @@
+-- Optional per-link fallback served once a short URL has expired
+ALTER TABLE short_urls
+ADD COLUMN expired_redirect_url TEXT NULL AFTER expiration;
@@ -0,0 +1,24 @@
+-- Custom aliases can be longer than generated codes, and every code must be unique.
+-- Codes are case-sensitive base62, so `aB3x` and `Ab3X` are different codes
+ALTER TABLE short_urls
+MODIFY short_code VARCHAR(32) CHARACTER SET utf8mb4 COLLATE utf8mb4_bin NOT NULL;
+
+-- The original generator gave the same URL the same code every time. The oldest link
+-- keeps a shared code; the others get their id without dashes, which is unique and
+-- cannot collide with the 10-character codes generated so far
+UPDATE short_urls s
+JOIN (
+    SELECT id
+    FROM (
+        SELECT id,
+               ROW_NUMBER() OVER (
+                   PARTITION BY short_code COLLATE utf8mb4_bin ORDER BY created_at, id
+               ) AS n
+        FROM short_urls
+    ) ranked
+    WHERE n > 1
+) duplicates ON duplicates.id = s.id
+SET s.short_code = REPLACE(s.id, '-', '');
+
+ALTER TABLE short_urls
+ADD UNIQUE INDEX idx_short_urls_short_code (short_code);
@@ -0,0 +1,7 @@
+-- Old short codes that keep redirecting for a grace period after a link gets a new code
+CREATE TABLE IF NOT EXISTS short_url_aliases (
+    short_code VARCHAR(32) CHARACTER SET utf8mb4 COLLATE utf8mb4_bin NOT NULL PRIMARY KEY,
+    short_url_id VARCHAR(36) NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    CONSTRAINT fk_alias_short_url FOREIGN KEY (short_url_id) REFERENCES short_urls (id) ON DELETE CASCADE
+);
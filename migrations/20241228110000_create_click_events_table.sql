@@ -0,0 +1,16 @@
+-- One row per redirect, used by the analytics endpoint
+CREATE TABLE IF NOT EXISTS click_events (
+    id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    short_url_id VARCHAR(36) NOT NULL,
+    clicked_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    referrer TEXT NULL,
+    user_agent TEXT NULL,
+    accept_language VARCHAR(255) NULL,
+    ip_address VARCHAR(45) NULL, -- anonymized before storage
+    browser VARCHAR(64) NULL,
+    os VARCHAR(64) NULL,
+    device_class VARCHAR(16) NULL,
+    language VARCHAR(35) NULL,
+    INDEX idx_click_events_url_time (short_url_id, clicked_at),
+    CONSTRAINT fk_click_short_url FOREIGN KEY (short_url_id) REFERENCES short_urls (id) ON DELETE CASCADE
+);
@@ -0,0 +1,3 @@
+-- HTTP status used for the redirect (301, 302, 307 or 308); NULL uses the server default
+ALTER TABLE short_urls
+ADD COLUMN redirect_type SMALLINT UNSIGNED NULL AFTER click_count;
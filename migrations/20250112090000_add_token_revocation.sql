@@ -0,0 +1,11 @@
+-- Access tokens revoked before their `exp`, keyed by the token's `jti`
+CREATE TABLE IF NOT EXISTS revoked_tokens (
+    jti VARCHAR(36) PRIMARY KEY,
+    user_id VARCHAR(36) NOT NULL,
+    revoked_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL,
+    INDEX idx_revoked_tokens_expires (expires_at)
+);
+
+-- Tokens issued before this instant (e.g. before a password change) are rejected
+ALTER TABLE users ADD COLUMN tokens_invalid_before TIMESTAMP NULL;
@@ -0,0 +1,21 @@
+-- Failed sign-in attempts, counted over a sliding window per email and per client IP
+CREATE TABLE IF NOT EXISTS login_failures (
+    id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    email VARCHAR(255) NOT NULL,
+    ip_address VARCHAR(45) NULL,
+    failed_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    INDEX idx_login_failures_email (email, failed_at),
+    INDEX idx_login_failures_ip (ip_address, failed_at),
+    INDEX idx_login_failures_time (failed_at)
+);
+
+-- Temporary sign-in lockouts; `scope` is `email` or `ip`
+CREATE TABLE IF NOT EXISTS login_lockouts (
+    id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    scope VARCHAR(8) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    failures INT UNSIGNED NOT NULL,
+    locked_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    locked_until TIMESTAMP NOT NULL,
+    UNIQUE INDEX idx_login_lockouts_subject (scope, subject)
+);
@@ -0,0 +1,20 @@
+-- TOTP (RFC 6238) second factor; `enabled_at` stays NULL until the first code is confirmed
+CREATE TABLE IF NOT EXISTS totp_credentials (
+    user_id VARCHAR(36) PRIMARY KEY,
+    secret VARCHAR(64) NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    enabled_at TIMESTAMP NULL,
+    last_used_step BIGINT UNSIGNED NULL, -- Time step of the last accepted code, to refuse replays
+    CONSTRAINT fk_totp_credential_user FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+-- One-time recovery codes for when the authenticator is lost; only the SHA-256 is stored
+CREATE TABLE IF NOT EXISTS recovery_codes (
+    id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    user_id VARCHAR(36) NOT NULL,
+    code_hash CHAR(64) NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    used_at TIMESTAMP NULL,
+    UNIQUE KEY uq_recovery_codes_user_code (user_id, code_hash),
+    CONSTRAINT fk_recovery_code_user FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
@@ -0,0 +1,11 @@
+-- Accounts at an OpenID Connect provider linked to users, keyed by the provider's `iss` and `sub`
+CREATE TABLE IF NOT EXISTS user_identities (
+    id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    user_id VARCHAR(36) NOT NULL,
+    issuer VARCHAR(255) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    last_login_at TIMESTAMP NULL,
+    UNIQUE KEY uq_user_identities_issuer_subject (issuer, subject),
+    CONSTRAINT fk_user_identity_user FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
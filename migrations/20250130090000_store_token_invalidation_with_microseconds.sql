@@ -0,0 +1,3 @@
+-- Access tokens carry their issue time in milliseconds, so a token issued in the same
+-- second as a password change can be told apart from one issued right after it
+ALTER TABLE users MODIFY tokens_invalid_before TIMESTAMP(6) NULL;
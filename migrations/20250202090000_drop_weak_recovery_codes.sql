@@ -0,0 +1,3 @@
+-- Recovery codes used to be 40 bits and hashed without a salt, weak enough to be found
+-- from a database dump. They are dropped; users get new ones by regenerating them.
+DELETE FROM recovery_codes;
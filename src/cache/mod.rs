@@ -0,0 +1,164 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use lru::LruCache;
+
+use crate::schema::url::ShortUrl;
+
+struct CacheEntry {
+    /// `None` records that the short code does not exist (negative caching).
+    url: Option<ShortUrl>,
+    inserted_at: Instant,
+}
+
+/// Bounded LRU cache with a TTL in front of the redirect lookup, keyed by short code.
+///
+/// A capacity of 0 disables caching entirely.
+pub struct RedirectCache {
+    entries: Option<Mutex<LruCache<String, CacheEntry>>>,
+    ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl RedirectCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            entries: NonZeroUsize::new(capacity)
+                .map(|capacity| Mutex::new(LruCache::new(capacity))),
+            ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Looks up a short code. `Some(None)` is a cached "not found".
+    pub fn get(&self, short_code: &str) -> Option<Option<ShortUrl>> {
+        let cached = self.entries.as_ref().and_then(|entries| {
+            let mut entries = entries.lock().unwrap();
+            match entries.get(short_code) {
+                Some(entry) if entry.inserted_at.elapsed() < self.ttl => Some(entry.url.clone()),
+                Some(_) => {
+                    entries.pop(short_code);
+                    None
+                }
+                None => None,
+            }
+        });
+
+        let counter = if cached.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        cached
+    }
+
+    /// Caches the lookup result for a short code, including "not found".
+    pub fn insert(&self, short_code: String, url: Option<ShortUrl>) {
+        if let Some(entries) = &self.entries {
+            entries.lock().unwrap().put(
+                short_code,
+                CacheEntry {
+                    url,
+                    inserted_at: Instant::now(),
+                },
+            );
+        }
+    }
+
+    /// Evicts a short code after the link behind it was created, changed or deleted.
+    pub fn invalidate(&self, short_code: &str) {
+        if let Some(entries) = &self.entries {
+            entries.lock().unwrap().pop(short_code);
+        }
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries
+            .as_ref()
+            .map(|entries| entries.lock().unwrap().len())
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(short_code: &str) -> ShortUrl {
+        ShortUrl {
+            short_code: short_code.to_string(),
+            ..ShortUrl::default()
+        }
+    }
+
+    #[test]
+    fn caches_lookups_and_counts_hits() {
+        let cache = RedirectCache::new(10, Duration::from_secs(60));
+        cache.insert("abc".to_string(), Some(url("abc")));
+        cache.insert("missing".to_string(), None);
+
+        assert_eq!(cache.get("abc").unwrap().unwrap().short_code, "abc");
+        assert!(cache.get("missing").unwrap().is_none());
+        assert!(cache.get("other").is_none());
+        assert_eq!((cache.hits(), cache.misses()), (2, 1));
+    }
+
+    #[test]
+    fn entries_expire_after_ttl() {
+        let cache = RedirectCache::new(10, Duration::from_millis(20));
+        cache.insert("abc".to_string(), Some(url("abc")));
+        std::thread::sleep(Duration::from_millis(30));
+
+        assert!(cache.get("abc").is_none());
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn evicts_least_recently_used_beyond_capacity() {
+        let cache = RedirectCache::new(2, Duration::from_secs(60));
+        cache.insert("a".to_string(), Some(url("a")));
+        cache.insert("b".to_string(), Some(url("b")));
+        cache.get("a");
+        cache.insert("c".to_string(), Some(url("c")));
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("c").is_some());
+    }
+
+    #[test]
+    fn invalidate_removes_an_entry() {
+        let cache = RedirectCache::new(10, Duration::from_secs(60));
+        cache.insert("abc".to_string(), None);
+        cache.invalidate("abc");
+
+        assert!(cache.get("abc").is_none());
+    }
+
+    #[test]
+    fn zero_capacity_disables_caching() {
+        let cache = RedirectCache::new(0, Duration::from_secs(60));
+        cache.insert("abc".to_string(), Some(url("abc")));
+
+        assert!(cache.get("abc").is_none());
+        assert_eq!(cache.len(), 0);
+    }
+}
@@ -0,0 +1,185 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    database::DatabasePool, schema::analytics::ClickEvent,
+    services::analytics_services::record_click_events,
+};
+
+enum ClickMessage {
+    Click(Box<ClickEvent>),
+    Shutdown(oneshot::Sender<()>),
+}
+
+/// Handle used by the redirect endpoint to count clicks without touching the database.
+///
+/// Clicks are sent to a background task that aggregates them per link and writes
+/// them to MySQL in batches.
+#[derive(Clone)]
+pub struct ClickCounter {
+    sender: mpsc::UnboundedSender<ClickMessage>,
+    pending: Arc<AtomicU64>,
+}
+
+impl ClickCounter {
+    /// Starts the background flusher and returns a handle to it.
+    ///
+    /// Pending clicks are written every `flush_interval`, or earlier once
+    /// `max_pending` clicks have accumulated. While the database is unavailable at
+    /// most `max_buffered` clicks are kept for retrying; older ones are dropped.
+    pub fn spawn(
+        db_pool: DatabasePool,
+        flush_interval: Duration,
+        max_pending: usize,
+        max_buffered: usize,
+    ) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let pending = Arc::new(AtomicU64::new(0));
+
+        let worker = ClickWorker {
+            db_pool,
+            pending: pending.clone(),
+            events: Vec::new(),
+            max_buffered,
+            failing: false,
+        };
+        actix_web::rt::spawn(worker.run(receiver, flush_interval, max_pending));
+
+        Self { sender, pending }
+    }
+
+    /// Queues one click on the link in `event`.
+    pub fn record(&self, event: ClickEvent) {
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        if self
+            .sender
+            .send(ClickMessage::Click(Box::new(event)))
+            .is_err()
+        {
+            self.pending.fetch_sub(1, Ordering::Relaxed);
+            eprintln!("Click counter is not running, dropping click");
+        }
+    }
+
+    /// Number of clicks received but not yet written to the database.
+    pub fn pending(&self) -> u64 {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    /// Flushes everything still pending and stops the background task.
+    pub async fn shutdown(&self) {
+        let (done, wait) = oneshot::channel();
+        if self.sender.send(ClickMessage::Shutdown(done)).is_ok() {
+            let _ = wait.await;
+        }
+    }
+}
+
+struct ClickWorker {
+    db_pool: DatabasePool,
+    pending: Arc<AtomicU64>,
+    events: Vec<ClickEvent>,
+    max_buffered: usize,
+    /// Set while flushes fail; retries then wait for the next tick.
+    failing: bool,
+}
+
+impl ClickWorker {
+    async fn run(
+        mut self,
+        mut receiver: mpsc::UnboundedReceiver<ClickMessage>,
+        flush_interval: Duration,
+        max_pending: usize,
+    ) {
+        let mut ticker = tokio::time::interval(flush_interval);
+
+        loop {
+            tokio::select! {
+                message = receiver.recv() => match message {
+                    Some(ClickMessage::Click(event)) => {
+                        self.events.push(*event);
+                        if self.events.len() >= max_pending && !self.failing {
+                            self.flush().await;
+                        }
+                        self.drop_overflow();
+                    }
+                    Some(ClickMessage::Shutdown(done)) => {
+                        self.flush().await;
+                        let _ = done.send(());
+                        return;
+                    }
+                    None => {
+                        self.flush().await;
+                        return;
+                    }
+                },
+                _ = ticker.tick() => self.flush().await,
+            }
+        }
+    }
+
+    /// Writes the per-link counts and the raw events in one transaction.
+    /// On failure the events are kept and retried on the next flush.
+    async fn flush(&mut self) {
+        if self.events.is_empty() {
+            return;
+        }
+
+        // Keyed by link id, so clicks survive the link getting a new short code
+        let mut counts = HashMap::<&str, u64>::new();
+        for event in &self.events {
+            *counts.entry(event.short_url_id.as_str()).or_insert(0) += 1;
+        }
+
+        let result = async {
+            let mut tx = self.db_pool.begin().await?;
+            for (short_url_id, clicks) in &counts {
+                sqlx::query(
+                    r#"
+                    UPDATE short_urls
+                    SET click_count = click_count + ?
+                    WHERE id = ?
+                    "#,
+                )
+                .bind(clicks)
+                .bind(short_url_id)
+                .execute(&mut *tx)
+                .await?;
+            }
+            record_click_events(&mut tx, &self.events).await?;
+            tx.commit().await
+        }
+        .await;
+
+        match result {
+            Ok(()) => {
+                self.pending
+                    .fetch_sub(self.events.len() as u64, Ordering::Relaxed);
+                self.events.clear();
+                self.failing = false;
+            }
+            Err(err) => {
+                eprintln!("Failed to flush click counts: {:?}", err);
+                self.failing = true;
+            }
+        }
+    }
+
+    /// Drops the oldest clicks beyond `max_buffered`, so an outage cannot exhaust memory.
+    fn drop_overflow(&mut self) {
+        let overflow = self.events.len().saturating_sub(self.max_buffered);
+        if overflow > 0 {
+            self.events.drain(..overflow);
+            self.pending.fetch_sub(overflow as u64, Ordering::Relaxed);
+            eprintln!("Click buffer is full, dropped {} clicks", overflow);
+        }
+    }
+}
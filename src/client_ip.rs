@@ -0,0 +1,184 @@
+use std::{net::IpAddr, str::FromStr};
+
+use actix_web::HttpRequest;
+
+/// Header a reverse proxy appends the address of its own peer to.
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// One trusted address or CIDR block, e.g. `10.0.0.0/8` or `::1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct IpBlock {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl IpBlock {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, canonical(ip)) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpBlock {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid trusted proxy: {}", s);
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+        let network = canonical(address.parse().map_err(|_| invalid())?);
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(invalid)?,
+            None => max_prefix,
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+/// IPv4 peers of a dual-stack socket show up as `::ffff:a.b.c.d`.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+/// Reverse proxies whose `X-Forwarded-For` header is believed.
+///
+/// Anyone can send the header, so it only counts when the connection comes from a
+/// trusted proxy. Empty by default: the socket address is then the client address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrustedProxies {
+    blocks: Vec<IpBlock>,
+}
+
+impl TrustedProxies {
+    /// Parses a comma-separated list of addresses and CIDR blocks.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let blocks = spec
+            .split(',')
+            .map(str::trim)
+            .filter(|block| !block.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { blocks })
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.blocks.iter().any(|block| block.contains(ip))
+    }
+
+    /// Finds the client address given the socket peer and the `X-Forwarded-For` value.
+    ///
+    /// Walks the header from the right, past the trusted proxies, and stops at the
+    /// first address they did not add themselves. Entries further left were written by
+    /// the client and are ignored.
+    pub fn client_ip(&self, peer: IpAddr, forwarded_for: Option<&str>) -> IpAddr {
+        let mut client = canonical(peer);
+        let Some(forwarded_for) = forwarded_for else {
+            return client;
+        };
+        for hop in forwarded_for.rsplit(',') {
+            if !self.is_trusted(client) {
+                break;
+            }
+            match hop.trim().parse::<IpAddr>() {
+                Ok(hop) => client = canonical(hop),
+                Err(_) => break,
+            }
+        }
+        client
+    }
+
+    /// The client address of a request, or `None` without a socket peer (in tests).
+    pub fn client_ip_of(&self, req: &HttpRequest) -> Option<IpAddr> {
+        let peer = req.peer_addr()?.ip();
+        // Proxies may add separate headers instead of extending one
+        let forwarded_for = req
+            .headers()
+            .get_all(X_FORWARDED_FOR)
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(",");
+        let forwarded_for = Some(forwarded_for.as_str()).filter(|value| !value.is_empty());
+        Some(self.client_ip(peer, forwarded_for))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_addresses_and_blocks() {
+        let proxies = TrustedProxies::parse("10.0.0.0/8, 192.168.1.1,fd00::/8").unwrap();
+
+        assert!(proxies.is_trusted(ip("10.1.2.3")));
+        assert!(proxies.is_trusted(ip("::ffff:10.1.2.3")));
+        assert!(proxies.is_trusted(ip("192.168.1.1")));
+        assert!(!proxies.is_trusted(ip("192.168.1.2")));
+        assert!(proxies.is_trusted(ip("fd12::1")));
+        assert!(!proxies.is_trusted(ip("2001:db8::1")));
+        assert!(TrustedProxies::parse("10.0.0.0/33").is_err());
+        assert!(TrustedProxies::parse("proxy.local").is_err());
+        assert_eq!(
+            TrustedProxies::parse("").unwrap(),
+            TrustedProxies::default()
+        );
+    }
+
+    #[test]
+    fn believes_forwarded_for_only_from_trusted_proxies() {
+        let proxies = TrustedProxies::parse("10.0.0.0/8").unwrap();
+        let header = Some("203.0.113.9, 198.51.100.7");
+
+        // Straight from the client: the header is made up
+        assert_eq!(
+            proxies.client_ip(ip("198.51.100.7"), header),
+            ip("198.51.100.7")
+        );
+        assert_eq!(
+            TrustedProxies::default().client_ip(ip("10.0.0.1"), header),
+            ip("10.0.0.1")
+        );
+        // Through the proxy: its peer is the client, the spoofed entry is ignored
+        assert_eq!(
+            proxies.client_ip(ip("10.0.0.1"), header),
+            ip("198.51.100.7")
+        );
+        // Through two trusted proxies
+        assert_eq!(
+            proxies.client_ip(ip("10.0.0.1"), Some("198.51.100.7, 10.0.0.2")),
+            ip("198.51.100.7")
+        );
+        assert_eq!(proxies.client_ip(ip("10.0.0.1"), None), ip("10.0.0.1"));
+        assert_eq!(
+            proxies.client_ip(ip("10.0.0.1"), Some("garbage")),
+            ip("10.0.0.1")
+        );
+    }
+}
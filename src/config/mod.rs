@@ -0,0 +1,614 @@
+use std::{env, fs};
+
+use actix_web::cookie::SameSite;
+use chrono::Duration;
+use url::Url;
+
+use actix_url_shortener::{
+    client_ip::TrustedProxies,
+    password::{PasswordAlgorithm, PasswordHashParams},
+    short_code::ShortCodeStrategy,
+    validation::{PasswordPolicy, UrlPolicy},
+};
+
+use crate::{
+    mail::MailTransport,
+    oidc::{OidcConfig, RoleMapping},
+    policy::Policy,
+    schema::{auth::RoleHierarchy, url::RedirectType},
+    throttle::LoginThrottle,
+};
+
+/// Branded page served with `410 Gone` when no custom page is configured.
+const DEFAULT_EXPIRED_LINK_PAGE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="utf-8">
+    <title>Link expired</title>
+</head>
+<body>
+    <h1>This link has expired</h1>
+    <p>The short link you followed is no longer available.</p>
+</body>
+</html>
+"#;
+
+/// What the redirect endpoint answers when a link has passed its `expiration`
+/// and has no per-link fallback URL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpiredLinkBehavior {
+    /// `410 Gone` with a JSON error body.
+    Gone,
+    /// `410 Gone` with the branded "link expired" HTML page.
+    Page,
+}
+
+/// Where the auth middleware may find an access token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenSource {
+    /// `Authorization: Bearer <token>`.
+    Header,
+    /// The `access_token` cookie set at sign-in.
+    Cookie,
+}
+
+/// Server-wide settings, loaded once at startup from the environment.
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    pub expired_link_behavior: ExpiredLinkBehavior,
+    pub expired_link_page: String,
+    pub short_code_strategy: ShortCodeStrategy,
+    pub short_code_length: usize,
+    pub short_code_salt: String,
+    pub short_code_max_attempts: u32,
+    pub code_alias_grace_period: Duration,
+    pub click_flush_interval: std::time::Duration,
+    pub click_flush_max_pending: usize,
+    pub click_max_buffered: usize,
+    pub redirect_cache_capacity: usize,
+    pub redirect_cache_ttl: std::time::Duration,
+    pub default_redirect_type: RedirectType,
+    pub url_policy: UrlPolicy,
+    pub public_hosts: Vec<String>,
+    pub max_redirect_chain_depth: usize,
+    pub policy: Policy,
+    pub access_token_ttl: Duration,
+    pub refresh_token_ttl: Duration,
+    pub token_source_precedence: Vec<TokenSource>,
+    pub cookie_same_site: SameSite,
+    pub cookie_secure: bool,
+    pub mail_transport: MailTransport,
+    pub mail_from: String,
+    pub mail_poll_interval: std::time::Duration,
+    pub password_reset_url: String,
+    pub password_reset_ttl: Duration,
+    pub password_reset_resend_interval: Duration,
+    pub email_verification_url: String,
+    pub email_verification_ttl: Duration,
+    pub verification_resend_interval: Duration,
+    pub login_throttle: LoginThrottle,
+    pub trusted_proxies: TrustedProxies,
+    pub mfa_issuer: String,
+    pub mfa_token_ttl: Duration,
+    pub password_login_enabled: bool,
+    pub oidc: Option<OidcConfig>,
+    pub password_algorithm: PasswordAlgorithm,
+    pub password_hash_params: PasswordHashParams,
+    pub password_policy: PasswordPolicy,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            expired_link_behavior: ExpiredLinkBehavior::Gone,
+            expired_link_page: DEFAULT_EXPIRED_LINK_PAGE.to_string(),
+            short_code_strategy: ShortCodeStrategy::Hash,
+            short_code_length: 10,
+            short_code_salt: String::new(),
+            short_code_max_attempts: 5,
+            code_alias_grace_period: Duration::days(30),
+            click_flush_interval: std::time::Duration::from_secs(5),
+            click_flush_max_pending: 1000,
+            click_max_buffered: 100_000,
+            redirect_cache_capacity: 10_000,
+            redirect_cache_ttl: std::time::Duration::from_secs(60),
+            default_redirect_type: RedirectType::Found,
+            url_policy: UrlPolicy::default(),
+            public_hosts: vec!["localhost:8080".to_string(), "127.0.0.1:8080".to_string()],
+            max_redirect_chain_depth: 1,
+            policy: Policy::default(),
+            access_token_ttl: Duration::minutes(15),
+            refresh_token_ttl: Duration::days(30),
+            token_source_precedence: vec![TokenSource::Header, TokenSource::Cookie],
+            cookie_same_site: SameSite::Lax,
+            cookie_secure: false,
+            // Emails carry sign-in links, which must not end up in the logs
+            mail_transport: MailTransport::File("mail".into()),
+            mail_from: "no-reply@localhost".to_string(),
+            mail_poll_interval: std::time::Duration::from_secs(5),
+            password_reset_url: "http://localhost:8080/reset-password".to_string(),
+            password_reset_ttl: Duration::hours(1),
+            password_reset_resend_interval: Duration::minutes(5),
+            email_verification_url: "http://localhost:8080/verify-email".to_string(),
+            email_verification_ttl: Duration::days(1),
+            verification_resend_interval: Duration::minutes(5),
+            login_throttle: LoginThrottle::default(),
+            trusted_proxies: TrustedProxies::default(),
+            mfa_issuer: "URL Shortener".to_string(),
+            mfa_token_ttl: Duration::minutes(5),
+            password_login_enabled: true,
+            oidc: None,
+            password_algorithm: PasswordAlgorithm::Argon2id,
+            password_hash_params: PasswordHashParams::default(),
+            password_policy: PasswordPolicy::default(),
+        }
+    }
+}
+
+impl AppConfig {
+    /// Builds the configuration from environment variables, falling back to defaults.
+    ///
+    /// - `EXPIRED_LINK_BEHAVIOR`: `gone` (default) or `page`
+    /// - `EXPIRED_LINK_PAGE`: path to an HTML file replacing the default expired page
+    /// - `SHORT_CODE_STRATEGY`: `hash` (default), `salted_hash`, `random` or `counter`
+    /// - `SHORT_CODE_LENGTH`: length of generated codes, 4 to 32 (default 10)
+    /// - `SHORT_CODE_SALT`: secret mixed into `salted_hash` codes
+    /// - `SHORT_CODE_MAX_ATTEMPTS`: how many codes to try before giving up on collisions
+    /// - `CODE_ALIAS_GRACE_PERIOD_SECS`: how long a replaced code keeps redirecting (default 30 days)
+    /// - `CLICK_FLUSH_INTERVAL_SECS`: how often queued clicks are written (default 5)
+    /// - `CLICK_FLUSH_MAX_PENDING`: queued clicks that trigger an early flush (default 1000)
+    /// - `CLICK_MAX_BUFFERED`: clicks kept for retrying while the database is down (default 100000)
+    /// - `REDIRECT_CACHE_CAPACITY`: short codes kept in the redirect cache, 0 disables it (default 10000)
+    /// - `REDIRECT_CACHE_TTL_SECS`: how long a cached lookup stays valid (default 60)
+    /// - `DEFAULT_REDIRECT_TYPE`: status for links without their own, 301/302/307/308 (default 302)
+    /// - `ALLOWED_URL_SCHEMES`: comma-separated destination schemes (default `http,https`)
+    /// - `MAX_URL_LENGTH`: longest destination URL accepted (default 2048)
+    /// - `PUBLIC_HOSTS`: comma-separated `host[:port]`s this service is reachable at, without a port only on the
+    ///   scheme's default one (default `localhost:8080,127.0.0.1:8080`)
+    /// - `MAX_REDIRECT_CHAIN_DEPTH`: short-link hops kept before a chain is flattened (default 1)
+    /// - `ROLE_HIERARCHY`: comma-separated `parent>child` role pairs (default `admin>user`)
+    /// - `ACCESS_TOKEN_TTL_SECS`: lifetime of access tokens (default 900)
+    /// - `REFRESH_TOKEN_TTL_SECS`: lifetime of refresh tokens (default 30 days)
+    /// - `TOKEN_SOURCE_PRECEDENCE`: comma-separated `header`/`cookie`, first found wins (default `header,cookie`)
+    /// - `COOKIE_SAME_SITE`: `strict`, `lax` (default) or `none` for the session cookies
+    /// - `COOKIE_SECURE`: send the session cookies over HTTPS only (default `false`, required with `none`)
+    /// - `MAIL_TRANSPORT`: `file:<directory>` (default `file:mail`), `smtp:<url>` or `log` (development only)
+    /// - `MAIL_FROM`: sender address of outgoing mail (default `no-reply@localhost`)
+    /// - `MAIL_POLL_INTERVAL_SECS`: how often the mail outbox is checked (default 5)
+    /// - `PASSWORD_RESET_URL`: page the reset link points to, `?token=...` is appended
+    /// - `PASSWORD_RESET_TTL_SECS`: how long a reset link stays valid (default 3600)
+    /// - `PASSWORD_RESET_RESEND_INTERVAL_SECS`: minimum time between reset emails (default 300)
+    /// - `EMAIL_VERIFICATION_URL`: page the verification link points to, `?token=...` is appended
+    /// - `EMAIL_VERIFICATION_TTL_SECS`: how long a verification link stays valid (default 1 day)
+    /// - `VERIFICATION_RESEND_INTERVAL_SECS`: minimum time between verification emails (default 300)
+    /// - `LOGIN_FAILURE_WINDOW_SECS`: how long failed sign-ins are counted (default 900)
+    /// - `LOGIN_DELAY_THRESHOLD`: failures per email before sign-ins are slowed down (default 3)
+    /// - `LOGIN_MAX_DELAY_MS`: longest delay applied to a sign-in attempt (default 8000)
+    /// - `LOGIN_LOCKOUT_THRESHOLD`: failures per email that lock it out (default 10)
+    /// - `LOGIN_IP_LOCKOUT_THRESHOLD`: failures per client IP that lock it out (default 50)
+    /// - `LOGIN_LOCKOUT_SECS`: how long a lockout lasts (default 900)
+    /// - `TRUSTED_PROXIES`: comma-separated proxy addresses or CIDR blocks whose `X-Forwarded-For` is believed (default none)
+    /// - `MFA_ISSUER`: name authenticator apps show next to the account (default `URL Shortener`)
+    /// - `MFA_TOKEN_TTL_SECS`: time to enter the second factor after the password (default 300)
+    /// - `PASSWORD_LOGIN_ENABLED`: allow signing in and up with a password (default `true`)
+    /// - `OIDC_ISSUER_URL`: OpenID Connect issuer, enables single sign-on under `/auth/oidc`
+    /// - `OIDC_CLIENT_ID`, `OIDC_CLIENT_SECRET`: client registration (the secret is optional with PKCE)
+    /// - `OIDC_REDIRECT_URL`: our callback as registered (default `http://localhost:8080/auth/oidc/callback`)
+    /// - `OIDC_SCOPES`: requested scopes (default `openid email profile`)
+    /// - `OIDC_GROUPS_CLAIM`: ID token claim holding the user's groups (default `groups`)
+    /// - `OIDC_ROLE_MAPPING`: comma-separated `group=role` pairs; when set, roles follow the groups
+    /// - `OIDC_POST_LOGIN_REDIRECT`: where the browser lands after signing in (default `/`)
+    /// - `PASSWORD_HASH_ALGORITHM`: `argon2id` (default) or `bcrypt` for new hashes; older ones are rehashed at sign-in
+    /// - `ARGON2_MEMORY_KIB`: Argon2id memory cost (default 19456)
+    /// - `ARGON2_ITERATIONS`: Argon2id time cost (default 2)
+    /// - `ARGON2_PARALLELISM`: Argon2id lanes (default 1)
+    /// - `BCRYPT_COST`: bcrypt cost, 4 to 31 (default 12)
+    /// - `PASSWORD_MIN_LENGTH`: shortest password accepted, in characters (default 8)
+    /// - `PASSWORD_MAX_LENGTH`: longest password accepted, in bytes (default 72, at most 72 with bcrypt)
+    /// - `BREACHED_PASSWORDS_FILE`: file of passwords that may not be chosen, one per line
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+
+        if let Ok(behavior) = env::var("EXPIRED_LINK_BEHAVIOR") {
+            config.expired_link_behavior = match behavior.to_lowercase().as_str() {
+                "gone" => ExpiredLinkBehavior::Gone,
+                "page" => ExpiredLinkBehavior::Page,
+                other => panic!("Invalid EXPIRED_LINK_BEHAVIOR: {}", other),
+            };
+        }
+
+        if let Ok(path) = env::var("EXPIRED_LINK_PAGE") {
+            config.expired_link_page =
+                fs::read_to_string(&path).expect("Failed to read EXPIRED_LINK_PAGE");
+        }
+
+        if let Ok(strategy) = env::var("SHORT_CODE_STRATEGY") {
+            config.short_code_strategy = strategy.parse().unwrap_or_else(|err| panic!("{}", err));
+        }
+
+        if let Ok(length) = env::var("SHORT_CODE_LENGTH") {
+            config.short_code_length = length
+                .parse()
+                .ok()
+                .filter(|length| (4..=32).contains(length))
+                .expect("SHORT_CODE_LENGTH must be a number between 4 and 32");
+        }
+
+        if let Ok(salt) = env::var("SHORT_CODE_SALT") {
+            config.short_code_salt = salt;
+        }
+        if config.short_code_strategy == ShortCodeStrategy::SaltedHash
+            && config.short_code_salt.is_empty()
+        {
+            panic!("SHORT_CODE_SALT must be set for the salted_hash strategy");
+        }
+
+        if let Ok(attempts) = env::var("SHORT_CODE_MAX_ATTEMPTS") {
+            config.short_code_max_attempts = attempts
+                .parse()
+                .ok()
+                .filter(|attempts| *attempts > 0)
+                .expect("SHORT_CODE_MAX_ATTEMPTS must be a positive number");
+        }
+
+        if let Ok(seconds) = env::var("CODE_ALIAS_GRACE_PERIOD_SECS") {
+            config.code_alias_grace_period = Duration::seconds(
+                seconds
+                    .parse()
+                    .expect("CODE_ALIAS_GRACE_PERIOD_SECS must be a number"),
+            );
+        }
+
+        if let Ok(seconds) = env::var("CLICK_FLUSH_INTERVAL_SECS") {
+            config.click_flush_interval = std::time::Duration::from_secs(
+                seconds
+                    .parse()
+                    .ok()
+                    .filter(|seconds| *seconds > 0)
+                    .expect("CLICK_FLUSH_INTERVAL_SECS must be a positive number"),
+            );
+        }
+
+        if let Ok(max_pending) = env::var("CLICK_FLUSH_MAX_PENDING") {
+            config.click_flush_max_pending = max_pending
+                .parse()
+                .expect("CLICK_FLUSH_MAX_PENDING must be a number");
+        }
+
+        if let Ok(max_buffered) = env::var("CLICK_MAX_BUFFERED") {
+            config.click_max_buffered = max_buffered
+                .parse()
+                .expect("CLICK_MAX_BUFFERED must be a number");
+        }
+
+        if let Ok(capacity) = env::var("REDIRECT_CACHE_CAPACITY") {
+            config.redirect_cache_capacity = capacity
+                .parse()
+                .expect("REDIRECT_CACHE_CAPACITY must be a number");
+        }
+
+        if let Ok(seconds) = env::var("REDIRECT_CACHE_TTL_SECS") {
+            config.redirect_cache_ttl = std::time::Duration::from_secs(
+                seconds
+                    .parse()
+                    .expect("REDIRECT_CACHE_TTL_SECS must be a number"),
+            );
+        }
+
+        if let Ok(status) = env::var("DEFAULT_REDIRECT_TYPE") {
+            config.default_redirect_type = status
+                .parse::<u16>()
+                .map_err(|err| err.to_string())
+                .and_then(RedirectType::try_from)
+                .unwrap_or_else(|err| panic!("Invalid DEFAULT_REDIRECT_TYPE: {}", err));
+        }
+
+        if let Ok(schemes) = env::var("ALLOWED_URL_SCHEMES") {
+            config.url_policy.allowed_schemes = schemes
+                .split(',')
+                .map(|scheme| scheme.trim().to_lowercase())
+                .filter(|scheme| !scheme.is_empty())
+                .collect();
+        }
+
+        if let Ok(length) = env::var("MAX_URL_LENGTH") {
+            config.url_policy.max_length = length.parse().expect("MAX_URL_LENGTH must be a number");
+        }
+
+        if let Ok(hosts) = env::var("PUBLIC_HOSTS") {
+            config.public_hosts = hosts
+                .split(',')
+                .map(|host| host.trim().to_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect();
+        }
+
+        if let Ok(depth) = env::var("MAX_REDIRECT_CHAIN_DEPTH") {
+            config.max_redirect_chain_depth = depth
+                .parse()
+                .expect("MAX_REDIRECT_CHAIN_DEPTH must be a number");
+        }
+
+        if let Ok(hierarchy) = env::var("ROLE_HIERARCHY") {
+            config.policy = Policy::new(
+                RoleHierarchy::parse(&hierarchy).unwrap_or_else(|err| panic!("{}", err)),
+            );
+        }
+
+        if let Ok(seconds) = env::var("ACCESS_TOKEN_TTL_SECS") {
+            config.access_token_ttl = Duration::seconds(
+                seconds
+                    .parse()
+                    .expect("ACCESS_TOKEN_TTL_SECS must be a number"),
+            );
+        }
+
+        if let Ok(seconds) = env::var("REFRESH_TOKEN_TTL_SECS") {
+            config.refresh_token_ttl = Duration::seconds(
+                seconds
+                    .parse()
+                    .expect("REFRESH_TOKEN_TTL_SECS must be a number"),
+            );
+        }
+
+        if let Ok(sources) = env::var("TOKEN_SOURCE_PRECEDENCE") {
+            config.token_source_precedence = sources
+                .split(',')
+                .map(|source| match source.trim().to_lowercase().as_str() {
+                    "header" => TokenSource::Header,
+                    "cookie" => TokenSource::Cookie,
+                    other => panic!("Invalid TOKEN_SOURCE_PRECEDENCE entry: {}", other),
+                })
+                .collect();
+        }
+
+        if let Ok(same_site) = env::var("COOKIE_SAME_SITE") {
+            config.cookie_same_site = match same_site.to_lowercase().as_str() {
+                "strict" => SameSite::Strict,
+                "lax" => SameSite::Lax,
+                "none" => SameSite::None,
+                other => panic!("Invalid COOKIE_SAME_SITE: {}", other),
+            };
+        }
+
+        if let Ok(secure) = env::var("COOKIE_SECURE") {
+            config.cookie_secure = secure.parse().expect("COOKIE_SECURE must be true or false");
+        }
+        if config.cookie_same_site == SameSite::None && !config.cookie_secure {
+            panic!("COOKIE_SECURE must be true when COOKIE_SAME_SITE is none");
+        }
+
+        if let Ok(transport) = env::var("MAIL_TRANSPORT") {
+            config.mail_transport = transport.parse().unwrap_or_else(|err| panic!("{}", err));
+        }
+
+        if let Ok(from) = env::var("MAIL_FROM") {
+            config.mail_from = from;
+        }
+
+        if let Ok(seconds) = env::var("MAIL_POLL_INTERVAL_SECS") {
+            config.mail_poll_interval = std::time::Duration::from_secs(
+                seconds
+                    .parse()
+                    .ok()
+                    .filter(|seconds| *seconds > 0)
+                    .expect("MAIL_POLL_INTERVAL_SECS must be a positive number"),
+            );
+        }
+
+        if let Ok(url) = env::var("PASSWORD_RESET_URL") {
+            config.password_reset_url = url;
+        }
+
+        if let Ok(seconds) = env::var("PASSWORD_RESET_TTL_SECS") {
+            config.password_reset_ttl = Duration::seconds(
+                seconds
+                    .parse()
+                    .expect("PASSWORD_RESET_TTL_SECS must be a number"),
+            );
+        }
+
+        if let Ok(seconds) = env::var("PASSWORD_RESET_RESEND_INTERVAL_SECS") {
+            config.password_reset_resend_interval = Duration::seconds(
+                seconds
+                    .parse()
+                    .expect("PASSWORD_RESET_RESEND_INTERVAL_SECS must be a number"),
+            );
+        }
+
+        if let Ok(url) = env::var("EMAIL_VERIFICATION_URL") {
+            config.email_verification_url = url;
+        }
+
+        if let Ok(seconds) = env::var("EMAIL_VERIFICATION_TTL_SECS") {
+            config.email_verification_ttl = Duration::seconds(
+                seconds
+                    .parse()
+                    .expect("EMAIL_VERIFICATION_TTL_SECS must be a number"),
+            );
+        }
+
+        if let Ok(seconds) = env::var("VERIFICATION_RESEND_INTERVAL_SECS") {
+            config.verification_resend_interval = Duration::seconds(
+                seconds
+                    .parse()
+                    .expect("VERIFICATION_RESEND_INTERVAL_SECS must be a number"),
+            );
+        }
+
+        if let Ok(seconds) = env::var("LOGIN_FAILURE_WINDOW_SECS") {
+            config.login_throttle.window = Duration::seconds(
+                seconds
+                    .parse()
+                    .expect("LOGIN_FAILURE_WINDOW_SECS must be a number"),
+            );
+        }
+
+        if let Ok(threshold) = env::var("LOGIN_DELAY_THRESHOLD") {
+            config.login_throttle.delay_after = threshold
+                .parse()
+                .expect("LOGIN_DELAY_THRESHOLD must be a number");
+        }
+
+        if let Ok(millis) = env::var("LOGIN_MAX_DELAY_MS") {
+            config.login_throttle.max_delay = std::time::Duration::from_millis(
+                millis.parse().expect("LOGIN_MAX_DELAY_MS must be a number"),
+            );
+        }
+
+        if let Ok(threshold) = env::var("LOGIN_LOCKOUT_THRESHOLD") {
+            config.login_throttle.email_lockout_threshold = threshold
+                .parse()
+                .expect("LOGIN_LOCKOUT_THRESHOLD must be a number");
+        }
+
+        if let Ok(threshold) = env::var("LOGIN_IP_LOCKOUT_THRESHOLD") {
+            config.login_throttle.ip_lockout_threshold = threshold
+                .parse()
+                .expect("LOGIN_IP_LOCKOUT_THRESHOLD must be a number");
+        }
+
+        if let Ok(seconds) = env::var("LOGIN_LOCKOUT_SECS") {
+            config.login_throttle.lockout_duration = Duration::seconds(
+                seconds
+                    .parse()
+                    .expect("LOGIN_LOCKOUT_SECS must be a number"),
+            );
+        }
+
+        if let Ok(proxies) = env::var("TRUSTED_PROXIES") {
+            config.trusted_proxies =
+                TrustedProxies::parse(&proxies).unwrap_or_else(|err| panic!("{}", err));
+        }
+
+        if let Ok(issuer) = env::var("MFA_ISSUER") {
+            if issuer.is_empty() || issuer.contains(':') {
+                panic!(
+                    "Invalid MFA_ISSUER: {}, must be non-empty without `:`",
+                    issuer
+                );
+            }
+            config.mfa_issuer = issuer;
+        }
+
+        if let Ok(seconds) = env::var("MFA_TOKEN_TTL_SECS") {
+            config.mfa_token_ttl = Duration::seconds(
+                seconds
+                    .parse()
+                    .expect("MFA_TOKEN_TTL_SECS must be a number"),
+            );
+        }
+
+        if let Ok(enabled) = env::var("PASSWORD_LOGIN_ENABLED") {
+            config.password_login_enabled = enabled
+                .parse()
+                .expect("PASSWORD_LOGIN_ENABLED must be true or false");
+        }
+
+        if let Ok(issuer_url) = env::var("OIDC_ISSUER_URL") {
+            let role_mapping = env::var("OIDC_ROLE_MAPPING")
+                .map(|spec| RoleMapping::parse(&spec).unwrap_or_else(|err| panic!("{}", err)))
+                .unwrap_or_default();
+            config.oidc = Some(OidcConfig {
+                issuer_url,
+                client_id: env::var("OIDC_CLIENT_ID")
+                    .expect("OIDC_CLIENT_ID must be set with OIDC_ISSUER_URL"),
+                client_secret: env::var("OIDC_CLIENT_SECRET")
+                    .ok()
+                    .filter(|secret| !secret.is_empty()),
+                redirect_url: env::var("OIDC_REDIRECT_URL")
+                    .unwrap_or_else(|_| "http://localhost:8080/auth/oidc/callback".to_string()),
+                scopes: env::var("OIDC_SCOPES")
+                    .unwrap_or_else(|_| "openid email profile".to_string()),
+                groups_claim: env::var("OIDC_GROUPS_CLAIM")
+                    .unwrap_or_else(|_| "groups".to_string()),
+                role_mapping,
+                post_login_redirect: env::var("OIDC_POST_LOGIN_REDIRECT")
+                    .unwrap_or_else(|_| "/".to_string()),
+            });
+        }
+        if !config.password_login_enabled && config.oidc.is_none() {
+            panic!("OIDC_ISSUER_URL must be set when PASSWORD_LOGIN_ENABLED is false");
+        }
+
+        if let Ok(algorithm) = env::var("PASSWORD_HASH_ALGORITHM") {
+            config.password_algorithm = algorithm.parse().unwrap_or_else(|err| panic!("{}", err));
+        }
+
+        if let Ok(memory) = env::var("ARGON2_MEMORY_KIB") {
+            config.password_hash_params.argon2_memory_kib =
+                memory.parse().expect("ARGON2_MEMORY_KIB must be a number");
+        }
+
+        if let Ok(iterations) = env::var("ARGON2_ITERATIONS") {
+            config.password_hash_params.argon2_iterations = iterations
+                .parse()
+                .expect("ARGON2_ITERATIONS must be a number");
+        }
+
+        if let Ok(parallelism) = env::var("ARGON2_PARALLELISM") {
+            config.password_hash_params.argon2_parallelism = parallelism
+                .parse()
+                .expect("ARGON2_PARALLELISM must be a number");
+        }
+
+        if let Ok(cost) = env::var("BCRYPT_COST") {
+            config.password_hash_params.bcrypt_cost =
+                cost.parse().expect("BCRYPT_COST must be a number");
+        }
+
+        if let Ok(length) = env::var("PASSWORD_MIN_LENGTH") {
+            config.password_policy.min_length = length
+                .parse()
+                .expect("PASSWORD_MIN_LENGTH must be a number");
+        }
+
+        if let Ok(length) = env::var("PASSWORD_MAX_LENGTH") {
+            config.password_policy.max_length = length
+                .parse()
+                .expect("PASSWORD_MAX_LENGTH must be a number");
+        }
+        // Longer passwords would be truncated, or rejected by the bcrypt crate
+        if config.password_algorithm == PasswordAlgorithm::Bcrypt
+            && config.password_policy.max_length > 72
+        {
+            panic!("PASSWORD_MAX_LENGTH must be at most 72 with bcrypt");
+        }
+
+        if let Ok(path) = env::var("BREACHED_PASSWORDS_FILE") {
+            config
+                .password_policy
+                .load_breached_passwords(&path)
+                .expect("Failed to read BREACHED_PASSWORDS_FILE");
+        }
+
+        config
+    }
+
+    /// Whether `url` points at one of `PUBLIC_HOSTS`. An entry without a port only
+    /// matches URLs on their scheme's default port.
+    pub fn is_public_host(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        self.public_hosts.iter().any(|public| {
+            let (public_host, public_port) = split_host_port(public);
+            let port_matches = match public_port {
+                Some(port) => url.port_or_known_default() == Some(port),
+                None => url.port().is_none(),
+            };
+            public_host.eq_ignore_ascii_case(host) && port_matches
+        })
+    }
+}
+
+/// Splits `host:port`, leaving IPv6 hosts like `[::1]` intact.
+fn split_host_port(entry: &str) -> (&str, Option<u16>) {
+    match entry.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') || host.ends_with(']') => match port.parse() {
+            Ok(port) => (host, Some(port)),
+            Err(_) => (entry, None),
+        },
+        _ => (entry, None),
+    }
+}
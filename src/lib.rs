@@ -1,15 +1,27 @@
-use bcrypt::BcryptError;
+use rand::RngCore;
 use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
+pub mod client_ip;
+pub mod password;
+pub mod short_code;
+pub mod validation;
+
 /// Function to generate a default UUID for the `id` field
 pub fn generate_uuid() -> String {
     Uuid::new_v4().to_string()
 }
 
-pub fn generate_password_hash(plain_password: &str) -> Result<String, BcryptError> {
-    let hashed_password = bcrypt::hash(plain_password, bcrypt::DEFAULT_COST)?;
-    Ok(hashed_password)
+/// Generates an unguessable token of `bytes` random bytes, hex-encoded.
+pub fn generate_secure_token(bytes: usize) -> String {
+    let mut buffer = vec![0u8; bytes];
+    rand::thread_rng().fill_bytes(&mut buffer);
+    hex::encode(buffer)
+}
+
+/// Hashes a bearer token for storage; only the hash is kept in the database.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
 }
 
 /// Generates a short code based on the hash of the original URL.
@@ -22,3 +34,56 @@ pub fn generate_short_code_from_url(original_url: &str, length: usize) -> String
     let short_code = hex::encode(result);
     short_code.chars().take(length).collect()
 }
+
+/// Paths and words that cannot be claimed as custom aliases.
+pub const RESERVED_ALIASES: &[&str] = &["urls", "users", "auth", "s", "api", "admin"];
+
+/// Allowed length range for custom aliases.
+pub const ALIAS_LENGTH: std::ops::RangeInclusive<usize> = 3..=32;
+
+/// Validates a user-chosen alias, returning a human-readable reason when it is rejected.
+pub fn validate_alias(alias: &str) -> Result<(), String> {
+    if !ALIAS_LENGTH.contains(&alias.len()) {
+        return Err(format!(
+            "Alias must be between {} and {} characters long",
+            ALIAS_LENGTH.start(),
+            ALIAS_LENGTH.end()
+        ));
+    }
+
+    if !alias
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err("Alias may only contain letters, digits, '-' and '_'".to_string());
+    }
+
+    if RESERVED_ALIASES.contains(&alias.to_lowercase().as_str()) {
+        return Err(format!("Alias '{}' is reserved", alias));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_well_formed_aliases() {
+        assert!(validate_alias("my-link_1").is_ok());
+        assert!(validate_alias("abc").is_ok());
+        assert!(validate_alias(&"a".repeat(32)).is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_length_charset_and_reserved_aliases() {
+        assert!(validate_alias("ab").is_err());
+        assert!(validate_alias(&"a".repeat(33)).is_err());
+        assert!(validate_alias("my link").is_err());
+        assert!(validate_alias("café").is_err());
+        assert!(validate_alias("a/b").is_err());
+        assert!(validate_alias("users").is_err());
+        assert!(validate_alias("Admin").is_err());
+    }
+}
@@ -1,13 +1,28 @@
+use actix_url_shortener::password::DummyPasswordHash;
 use actix_web::{
     middleware::{from_fn, Logger},
     web::{self, Data},
     App, HttpServer,
 };
+use cache::RedirectCache;
+use clicks::ClickCounter;
+use config::AppConfig;
 use database::init_db;
 use dotenv::dotenv;
+use keys::KeyStore;
 use middleware::verify_jwt_and_role;
+use oidc::OidcClient;
 use services::{
-    auth_services::{login_user, register_user},
+    analytics_services::get_url_analytics,
+    api_key_services::{create_api_key, list_api_keys, rename_api_key, revoke_api_key},
+    auth_services::{
+        forgot_password, login_user, logout_user, refresh_session, register_user,
+        resend_verification, reset_password, verify_email,
+    },
+    lockout_services::{delete_lockout, list_active_lockouts},
+    metrics_services::metrics,
+    mfa_services::{confirm_mfa, disable_mfa, enroll_mfa, regenerate_recovery_codes, verify_mfa},
+    oidc_services::{oidc_callback, oidc_login},
     url_services::{
         create_short_url, delete_url, get_short_url_by_id, redirect_to_original, update_url,
     },
@@ -18,10 +33,19 @@ use services::{
 };
 
 use std::io;
+mod cache;
+mod clicks;
+mod config;
 mod database;
+mod keys;
+mod mail;
+mod mfa;
 mod middleware;
+mod oidc;
+mod policy;
 mod schema;
 mod services;
+mod throttle;
 
 #[actix_web::main]
 async fn main() -> io::Result<()> {
@@ -30,21 +54,91 @@ async fn main() -> io::Result<()> {
     env_logger::init();
 
     let db = init_db().await.expect("Failed to initialize database");
+    let config = AppConfig::from_env();
+    let keys = Data::new(KeyStore::from_env().map_err(io::Error::other)?);
+    let short_code_generator = config
+        .short_code_strategy
+        .build(config.short_code_length, &config.short_code_salt);
+    let password_hasher = config
+        .password_algorithm
+        .build(&config.password_hash_params)
+        .map_err(io::Error::other)?;
+    let dummy_password_hash =
+        Data::new(DummyPasswordHash::new(password_hasher.as_ref()).map_err(io::Error::other)?);
+    let clicks = ClickCounter::spawn(
+        db.clone(),
+        config.click_flush_interval,
+        config.click_flush_max_pending,
+        config.click_max_buffered,
+    );
+    let app_clicks = clicks.clone();
+    let mailer = config
+        .mail_transport
+        .build(&config.mail_from)
+        .map_err(io::Error::other)?;
+    mail::spawn_outbox_worker(db.clone(), mailer, config.mail_poll_interval);
+    let cache = Data::new(RedirectCache::new(
+        config.redirect_cache_capacity,
+        config.redirect_cache_ttl,
+    ));
+    let oidc = config
+        .oidc
+        .clone()
+        .map(|oidc| Data::new(OidcClient::new(oidc)));
 
     HttpServer::new(move || {
         App::new()
             .app_data(Data::new(db.clone()))
+            .app_data(Data::new(config.clone()))
+            .app_data(keys.clone())
+            .app_data(Data::from(short_code_generator.clone()))
+            .app_data(Data::from(password_hasher.clone()))
+            .app_data(dummy_password_hash.clone())
+            .app_data(Data::new(app_clicks.clone()))
+            .app_data(cache.clone())
+            .configure(|cfg| {
+                // Single sign-on routes answer 404 without a client
+                if let Some(oidc) = &oidc {
+                    cfg.app_data(oidc.clone());
+                }
+            })
             .wrap(Logger::default()) // Logs requests automatically
             // Public route, no middleware
             .service(redirect_to_original)
+            .service(metrics)
             // Routes requiring 'user' role
             .service(
                 web::scope("/urls")
                     .service(create_short_url)
                     .service(update_url)
                     .service(delete_url)
+                    .service(get_url_analytics)
                     .service(get_short_url_by_id)
-                    .wrap(from_fn(|req, next| verify_jwt_and_role(req, next, "user"))),
+                    .wrap(from_fn(|req, next| {
+                        verify_jwt_and_role(req, next, &["user"])
+                    })),
+            )
+            // API keys of the signed-in user
+            .service(
+                web::scope("/api-keys")
+                    .service(create_api_key)
+                    .service(list_api_keys)
+                    .service(rename_api_key)
+                    .service(revoke_api_key)
+                    .wrap(from_fn(|req, next| {
+                        verify_jwt_and_role(req, next, &["user"])
+                    })),
+            )
+            // Two-factor settings of the signed-in user
+            .service(
+                web::scope("/mfa")
+                    .service(enroll_mfa)
+                    .service(confirm_mfa)
+                    .service(regenerate_recovery_codes)
+                    .service(disable_mfa)
+                    .wrap(from_fn(|req, next| {
+                        verify_jwt_and_role(req, next, &["user"])
+                    })),
             )
             // Routes requiring 'admin' role
             .service(
@@ -56,15 +150,38 @@ async fn main() -> io::Result<()> {
                     .service(delete_user_by_id)
                     .service(list_user_urls)
                     .service(update_user_by_id)
-                    .wrap(from_fn(|req, next| verify_jwt_and_role(req, next, "admin"))),
+                    .wrap(from_fn(|req, next| {
+                        verify_jwt_and_role(req, next, &["admin"])
+                    })),
+            )
+            .service(
+                web::scope("/lockouts")
+                    .service(list_active_lockouts)
+                    .service(delete_lockout)
+                    .wrap(from_fn(|req, next| {
+                        verify_jwt_and_role(req, next, &["admin"])
+                    })),
             )
             .service(
                 web::scope("/auth")
                     .service(login_user)
-                    .service(register_user),
+                    .service(register_user)
+                    .service(refresh_session)
+                    .service(logout_user)
+                    .service(forgot_password)
+                    .service(reset_password)
+                    .service(verify_email)
+                    .service(resend_verification)
+                    .service(verify_mfa)
+                    .service(oidc_login)
+                    .service(oidc_callback),
             )
     })
     .bind(("127.0.0.1", 8080))?
     .run()
-    .await
+    .await?;
+
+    // The server has stopped accepting requests; write out the clicks still in memory
+    clicks.shutdown().await;
+    Ok(())
 }
@@ -0,0 +1,280 @@
+use actix_url_shortener::{generate_secure_token, hash_token};
+use chrono::{DateTime, Utc};
+use qrcode::{render::svg, QrCode};
+use rand::RngCore;
+use sqlx::{prelude::FromRow, MySql, Transaction};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::database::DatabasePool;
+
+/// Digits per code, as expected by common authenticator apps.
+const TOTP_DIGITS: usize = 6;
+
+/// Seconds each code is valid for.
+const TOTP_STEP: u64 = 30;
+
+/// Steps accepted on either side of the current one, to allow for clock drift.
+const TOTP_SKEW: u64 = 1;
+
+/// Recovery codes handed out per enrollment.
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Random bytes per recovery code: 80 bits, out of reach of an offline search even
+/// with the hashes at hand.
+const RECOVERY_CODE_BYTES: usize = 10;
+
+/// Hex digits between the dashes of a recovery code, for readability.
+const RECOVERY_CODE_GROUP: usize = 5;
+
+/// A user's TOTP secret, enabled once a first code was confirmed.
+#[derive(Debug, Clone, FromRow)]
+pub struct TotpCredential {
+    pub secret: String,
+    pub enabled_at: Option<DateTime<Utc>>,
+}
+
+impl TotpCredential {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled_at.is_some()
+    }
+}
+
+/// Generates a base32-encoded 160-bit secret, the size RFC 4226 recommends.
+pub fn generate_secret() -> String {
+    let mut bytes = vec![0u8; 20];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    Secret::Raw(bytes).to_encoded().to_string()
+}
+
+/// The `otpauth://` URI authenticator apps import, labelled with `issuer` and `account`.
+pub fn provisioning_uri(secret: &str, issuer: &str, account: &str) -> Result<String, String> {
+    let totp = TOTP::new(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        TOTP_SKEW as u8,
+        TOTP_STEP,
+        decode_secret(secret)?,
+        Some(issuer.to_string()),
+        account.to_string(),
+    )
+    .map_err(|err| err.to_string())?;
+    Ok(totp.get_url())
+}
+
+/// Renders `uri` as an SVG QR code.
+pub fn qr_code_svg(uri: &str) -> Result<String, String> {
+    let code = QrCode::new(uri.as_bytes()).map_err(|err| err.to_string())?;
+    Ok(code.render::<svg::Color>().min_dimensions(200, 200).build())
+}
+
+/// Returns the time step `code` is valid for at `time`, within the allowed skew.
+pub fn matching_step(secret: &str, code: &str, time: u64) -> Option<u64> {
+    let totp = TOTP::new_unchecked(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        TOTP_SKEW as u8,
+        TOTP_STEP,
+        decode_secret(secret).ok()?,
+        None,
+        String::new(),
+    );
+    let current = time / TOTP_STEP;
+    (current.saturating_sub(TOTP_SKEW)..=current + TOTP_SKEW)
+        .find(|step| totp.generate(step * TOTP_STEP) == code)
+}
+
+/// Generates a fresh set of recovery codes, e.g. `3f9a1-c07e2-88b0d-41fa6`.
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = generate_secure_token(RECOVERY_CODE_BYTES);
+            code.as_bytes()
+                .chunks(RECOVERY_CODE_GROUP)
+                .map(|group| std::str::from_utf8(group).unwrap())
+                .collect::<Vec<_>>()
+                .join("-")
+        })
+        .collect()
+}
+
+/// Hash a recovery code is stored under. Salted with the user id, so the same work
+/// does not crack the codes of every user at once.
+fn hash_recovery_code(user_id: &str, code: &str) -> String {
+    hash_token(&format!("{}:{}", user_id, normalize_recovery_code(code)))
+}
+
+/// Recovery codes are matched ignoring case, dashes and spaces.
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect::<String>()
+        .to_lowercase()
+}
+
+fn decode_secret(secret: &str) -> Result<Vec<u8>, String> {
+    Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|err| format!("Invalid TOTP secret: {:?}", err))
+}
+
+/// Loads a user's TOTP credential, enabled or not.
+pub async fn find_credential(
+    db_pool: &DatabasePool,
+    user_id: &str,
+) -> Result<Option<TotpCredential>, sqlx::Error> {
+    sqlx::query_as::<_, TotpCredential>(
+        r#"
+        SELECT secret, enabled_at
+        FROM totp_credentials
+        WHERE user_id = ?
+        "#,
+    )
+    .bind(user_id)
+    .fetch_optional(db_pool)
+    .await
+}
+
+/// Whether sign-ins of this user need a second factor.
+pub async fn is_enabled(db_pool: &DatabasePool, user_id: &str) -> Result<bool, sqlx::Error> {
+    Ok(find_credential(db_pool, user_id)
+        .await?
+        .is_some_and(|credential| credential.is_enabled()))
+}
+
+/// Checks a TOTP code against `credential` and records its step, so the same code
+/// cannot be used twice.
+pub async fn accept_totp(
+    db_pool: &DatabasePool,
+    user_id: &str,
+    credential: &TotpCredential,
+    code: &str,
+) -> Result<bool, sqlx::Error> {
+    let Some(step) = matching_step(
+        &credential.secret,
+        code.trim(),
+        Utc::now().timestamp() as u64,
+    ) else {
+        return Ok(false);
+    };
+
+    let result = sqlx::query(
+        r#"
+        UPDATE totp_credentials
+        SET last_used_step = ?
+        WHERE user_id = ? AND (last_used_step IS NULL OR last_used_step < ?)
+        "#,
+    )
+    .bind(step)
+    .bind(user_id)
+    .bind(step)
+    .execute(db_pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Checks the second factor of a sign-in: a code from the authenticator app, or
+/// one of the user's unused recovery codes, which is then used up.
+pub async fn verify_second_factor(
+    db_pool: &DatabasePool,
+    user_id: &str,
+    code: &str,
+) -> Result<bool, sqlx::Error> {
+    let code = code.trim();
+    if code.len() == TOTP_DIGITS && code.chars().all(|c| c.is_ascii_digit()) {
+        return match find_credential(db_pool, user_id).await? {
+            Some(credential) if credential.is_enabled() => {
+                accept_totp(db_pool, user_id, &credential, code).await
+            }
+            _ => Ok(false),
+        };
+    }
+
+    let result = sqlx::query(
+        r#"
+        UPDATE recovery_codes
+        SET used_at = ?
+        WHERE user_id = ? AND code_hash = ? AND used_at IS NULL
+        "#,
+    )
+    .bind(Utc::now())
+    .bind(user_id)
+    .bind(hash_recovery_code(user_id, code))
+    .execute(db_pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Replaces the user's recovery codes with a new set and returns them in clear text.
+pub async fn replace_recovery_codes(
+    tx: &mut Transaction<'_, MySql>,
+    user_id: &str,
+) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    let codes = generate_recovery_codes();
+    for code in &codes {
+        sqlx::query("INSERT INTO recovery_codes (user_id, code_hash) VALUES (?, ?)")
+            .bind(user_id)
+            .bind(hash_recovery_code(user_id, code))
+            .execute(&mut **tx)
+            .await?;
+    }
+
+    Ok(codes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The RFC 6238 test secret, `12345678901234567890` in base32.
+    const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn matches_rfc_6238_codes_within_skew() {
+        // Appendix B: at 59 seconds, the SHA-1 code truncated to 6 digits is 287082
+        assert_eq!(matching_step(SECRET, "287082", 59), Some(1));
+        assert_eq!(matching_step(SECRET, "287082", 59 + 30), Some(1));
+        assert_eq!(matching_step(SECRET, "287082", 59 + 60), None);
+        assert_eq!(matching_step(SECRET, "000000", 59), None);
+    }
+
+    #[test]
+    fn recovery_codes_are_unique_and_normalized() {
+        let codes = generate_recovery_codes();
+        let mut unique = codes.clone();
+        unique.sort();
+        unique.dedup();
+
+        assert_eq!(unique.len(), RECOVERY_CODE_COUNT);
+        assert!(codes
+            .iter()
+            .all(|code| code.len() == 23 && code.matches('-').count() == 3));
+        assert_eq!(
+            normalize_recovery_code(" 3F9A1-C07E2 "),
+            normalize_recovery_code("3f9a1c07e2")
+        );
+        assert_eq!(
+            hash_recovery_code("alice", " 3F9A1-C07E2 "),
+            hash_recovery_code("alice", "3f9a1c07e2")
+        );
+        assert_ne!(
+            hash_recovery_code("alice", "3f9a1c07e2"),
+            hash_recovery_code("bob", "3f9a1c07e2")
+        );
+    }
+
+    #[test]
+    fn provisioning_uri_names_issuer_and_account() {
+        let uri = provisioning_uri(SECRET, "Shortener", "alice@example.com").unwrap();
+
+        assert!(uri.starts_with("otpauth://totp/Shortener:alice%40example.com?"));
+        assert!(uri.contains(&format!("secret={}", SECRET)));
+        assert!(qr_code_svg(&uri).unwrap().starts_with("<?xml"));
+    }
+}
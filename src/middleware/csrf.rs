@@ -0,0 +1,210 @@
+use actix_url_shortener::hash_token;
+use actix_web::error::ErrorForbidden;
+use actix_web::http::header::{ORIGIN, REFERER};
+use actix_web::http::Method;
+use actix_web::{Error, HttpRequest};
+use url::Url;
+
+use crate::config::AppConfig;
+use crate::services::token_services::{
+    ACCESS_TOKEN_COOKIE, CSRF_TOKEN_COOKIE, REFRESH_TOKEN_COOKIE,
+};
+
+/// Header in which clients echo the `csrf_token` cookie.
+pub const CSRF_TOKEN_HEADER: &str = "X-CSRF-Token";
+
+pub fn is_safe_method(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    )
+}
+
+/// Guards a cookie-authenticated, state-changing request against cross-site forgery.
+///
+/// The request must come from one of our own origins, and must carry the
+/// `csrf_token` cookie's value in `X-CSRF-Token`, which a cross-site page cannot read.
+pub fn check_csrf(req: &HttpRequest, config: &AppConfig) -> Result<(), Error> {
+    check_same_origin(req, config)?;
+
+    let cookie = req
+        .cookie(CSRF_TOKEN_COOKIE)
+        .ok_or_else(|| ErrorForbidden("Missing CSRF cookie"))?;
+    let header = req
+        .headers()
+        .get(CSRF_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| ErrorForbidden("Missing CSRF token"))?;
+
+    // Comparing digests keeps the comparison time independent of the token
+    if !cookie.value().is_empty() && hash_token(cookie.value()) == hash_token(header) {
+        Ok(())
+    } else {
+        Err(ErrorForbidden("Invalid CSRF token"))
+    }
+}
+
+/// Guards the auth endpoints that act on the session cookies themselves, such as
+/// refreshing and signing out, which the JWT middleware does not cover. Requests
+/// without a session cookie, e.g. from Bearer clients, have nothing to forge.
+pub fn check_session_csrf(req: &HttpRequest, config: &AppConfig) -> Result<(), Error> {
+    let has_session_cookie = [ACCESS_TOKEN_COOKIE, REFRESH_TOKEN_COOKIE]
+        .iter()
+        .any(|name| req.cookie(name).is_some());
+    if has_session_cookie {
+        check_csrf(req, config)
+    } else {
+        Ok(())
+    }
+}
+
+/// The `Origin` (or, failing that, `Referer`) must name the host the request was
+/// sent to or one of `PUBLIC_HOSTS`.
+fn check_same_origin(req: &HttpRequest, config: &AppConfig) -> Result<(), Error> {
+    let origin = req
+        .headers()
+        .get(ORIGIN)
+        .or_else(|| req.headers().get(REFERER))
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| Url::parse(value).ok())
+        .ok_or_else(|| ErrorForbidden("Missing Origin header"))?;
+
+    let origin_host = match (origin.host_str(), origin.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (Some(host), None) => host.to_string(),
+        (None, _) => return Err(ErrorForbidden("Cross-origin request rejected")),
+    };
+    let request_host = req.connection_info().host().to_lowercase();
+
+    if origin_host == request_host || config.is_public_host(&origin) {
+        Ok(())
+    } else {
+        Err(ErrorForbidden("Cross-origin request rejected"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        cookie::Cookie,
+        http::{header::CONTENT_TYPE, StatusCode},
+        test::TestRequest,
+    };
+
+    use super::*;
+
+    const TOKEN: &str = "5f2b8c1e9a7d4f3b";
+
+    fn config() -> AppConfig {
+        AppConfig {
+            public_hosts: vec!["sho.rt".to_string()],
+            ..AppConfig::default()
+        }
+    }
+
+    /// A cookie-authenticated POST to `/urls/` as sent to `sho.rt`.
+    fn post() -> TestRequest {
+        TestRequest::post()
+            .uri("/urls/")
+            .insert_header(("Host", "sho.rt"))
+            .cookie(Cookie::new("access_token", "jwt"))
+            .cookie(Cookie::new(CSRF_TOKEN_COOKIE, TOKEN))
+    }
+
+    fn rejected(req: TestRequest) -> bool {
+        check_csrf(&req.to_http_request(), &config())
+            .is_err_and(|err| err.as_response_error().status_code() == StatusCode::FORBIDDEN)
+    }
+
+    #[test]
+    fn cross_origin_form_post_is_rejected() {
+        let req = post()
+            .insert_header((ORIGIN, "https://evil.example"))
+            .insert_header((CONTENT_TYPE, "application/x-www-form-urlencoded"))
+            .set_payload("originalUrl=https%3A%2F%2Fevil.example");
+
+        assert!(rejected(req));
+    }
+
+    #[test]
+    fn cross_origin_post_is_rejected_even_with_a_token() {
+        let req = post()
+            .insert_header((ORIGIN, "https://evil.example"))
+            .insert_header((CSRF_TOKEN_HEADER, TOKEN));
+
+        assert!(rejected(req));
+    }
+
+    #[test]
+    fn cross_origin_referer_is_rejected_without_origin() {
+        let req = post()
+            .insert_header((REFERER, "https://evil.example/page"))
+            .insert_header((CSRF_TOKEN_HEADER, TOKEN));
+
+        assert!(rejected(req));
+    }
+
+    #[test]
+    fn post_without_origin_or_referer_is_rejected() {
+        assert!(rejected(post().insert_header((CSRF_TOKEN_HEADER, TOKEN))));
+    }
+
+    #[test]
+    fn same_origin_post_needs_matching_token() {
+        let same_origin = || post().insert_header((ORIGIN, "https://sho.rt"));
+
+        assert!(rejected(same_origin()));
+        assert!(rejected(
+            same_origin().insert_header((CSRF_TOKEN_HEADER, "guessed"))
+        ));
+        assert!(!rejected(
+            same_origin().insert_header((CSRF_TOKEN_HEADER, TOKEN))
+        ));
+    }
+
+    #[test]
+    fn same_origin_referer_is_accepted() {
+        let req = post()
+            .insert_header((REFERER, "https://sho.rt/dashboard"))
+            .insert_header((CSRF_TOKEN_HEADER, TOKEN));
+
+        assert!(!rejected(req));
+    }
+
+    #[test]
+    fn session_endpoints_are_checked_when_a_session_cookie_is_sent() {
+        let refresh = || {
+            TestRequest::post()
+                .uri("/auth/refresh")
+                .insert_header(("Host", "sho.rt"))
+                .cookie(Cookie::new(REFRESH_TOKEN_COOKIE, "refresh"))
+                .cookie(Cookie::new(CSRF_TOKEN_COOKIE, TOKEN))
+        };
+        let checked = |req: TestRequest| check_session_csrf(&req.to_http_request(), &config());
+
+        assert!(checked(refresh().insert_header((ORIGIN, "https://evil.example"))).is_err());
+        assert!(checked(refresh().insert_header((ORIGIN, "https://sho.rt"))).is_err());
+        assert!(checked(
+            refresh()
+                .insert_header((ORIGIN, "https://sho.rt"))
+                .insert_header((CSRF_TOKEN_HEADER, TOKEN))
+        )
+        .is_ok());
+        // A Bearer client signing out sends no cookies
+        assert!(checked(
+            TestRequest::post()
+                .uri("/auth/sign-out")
+                .insert_header(("Authorization", "Bearer jwt"))
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn only_unsafe_methods_are_checked() {
+        assert!(is_safe_method(&Method::GET));
+        assert!(is_safe_method(&Method::HEAD));
+        for method in [Method::POST, Method::PUT, Method::PATCH, Method::DELETE] {
+            assert!(!is_safe_method(&method));
+        }
+    }
+}
@@ -1,45 +1,194 @@
+use crate::config::{AppConfig, TokenSource};
+use crate::database::DatabasePool;
+use crate::keys::KeyStore;
+use crate::schema::api_key::API_KEY_PREFIX;
 use crate::schema::auth::Claims;
+use crate::services::api_key_services::authenticate_api_key;
+use crate::services::token_services::{
+    access_token_status, decode_access_token, TokenStatus, ACCESS_TOKEN_COOKIE,
+};
 use actix_web::body::MessageBody;
 use actix_web::dev::ServiceResponse;
-use actix_web::error::ErrorUnauthorized;
-use actix_web::HttpMessage;
+use actix_web::error::{ErrorInternalServerError, ErrorUnauthorized};
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::web::Data;
 use actix_web::{dev::ServiceRequest, middleware::Next, Error};
-use jsonwebtoken::{decode, DecodingKey, Validation};
+use actix_web::{HttpMessage, HttpRequest};
+use csrf::{check_csrf, is_safe_method};
 
-/// Middleware to verify the JWT and check for required roles
+mod csrf;
+
+pub(crate) use csrf::check_session_csrf;
+
+/// Header carrying an API key for clients that cannot set `Authorization`.
+const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// How the caller authenticated, stored in the request extensions next to the claims.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthSource {
+    /// A personal API key, from `X-Api-Key` or `Authorization: Bearer usk_...`.
+    ApiKey,
+    /// A JWT from `Authorization: Bearer`.
+    Bearer,
+    /// A JWT from the `access_token` cookie, which browsers attach on their own.
+    Cookie,
+}
+
+/// A credential found on the request, before it is verified.
+pub(crate) enum Credential {
+    ApiKey(String),
+    Token(String, AuthSource),
+}
+
+/// Middleware to verify the caller's credentials and check that the user holds at
+/// least one of the required roles, either directly or through the configured role
+/// hierarchy.
+///
+/// `X-Api-Key` always wins; otherwise the Bearer header and the `access_token` cookie
+/// are tried in the configured order. Tokens that were revoked server-side or belong to
+/// an inactive user are rejected even before they expire, and cookie-authenticated mutations must pass the CSRF
+/// checks.
 pub async fn verify_jwt_and_role(
     req: ServiceRequest,
     next: Next<impl MessageBody>,
-    required_roles: &str,
+    required_roles: &[&str],
 ) -> Result<ServiceResponse<impl MessageBody>, Error> {
-    if let Some(cookie) = req.cookie("access_token") {
-        let token = cookie.value().to_string();
-        let secret_key = std::env::var("SECRET").unwrap_or_else(|_| "default_secret".to_string());
-
-        // Decode and validate the token
-        match decode::<Claims>(
-            &token,
-            &DecodingKey::from_secret(secret_key.as_bytes()),
-            &Validation::default(),
-        ) {
-            Ok(token_data) => {
-                if token_data.claims.roles == required_roles {
-                    // Store Claims in the request extensions
-                    req.extensions_mut().insert(token_data.claims);
-                    next.call(req).await
-                } else {
-                    Err(ErrorUnauthorized(format!(
-                        "Access denied. Required roles: {:?}, but found: {:?}",
-                        required_roles, token_data.claims.roles
-                    )))
-                }
+    let db_pool = req
+        .app_data::<Data<DatabasePool>>()
+        .expect("DatabasePool must be registered as app data");
+    let config = req
+        .app_data::<Data<AppConfig>>()
+        .expect("AppConfig must be registered as app data");
+
+    let (claims, source) = match find_credential(req.request(), config) {
+        Some(Credential::ApiKey(api_key)) => match authenticate_api_key(db_pool, &api_key).await {
+            Ok(Some(claims)) => (claims, AuthSource::ApiKey),
+            Ok(None) => return Err(ErrorUnauthorized("Invalid, expired or revoked API key")),
+            Err(err) => {
+                eprintln!("Failed to verify API key: {:?}", err);
+                return Err(ErrorInternalServerError("Failed to verify API key"));
             }
-            Err(err) => Err(ErrorUnauthorized(format!(
-                "Invalid or expired token: {}",
-                err
-            ))),
+        },
+        Some(Credential::Token(token, source)) => {
+            let keys = req
+                .app_data::<Data<KeyStore>>()
+                .expect("KeyStore must be registered as app data");
+            (verify_access_token(keys, db_pool, &token).await?, source)
         }
+        None => return Err(ErrorUnauthorized("Missing access token")),
+    };
+
+    if source == AuthSource::Cookie && !is_safe_method(req.method()) {
+        check_csrf(req.request(), config)?;
+    }
+
+    if config.policy.grants_any_role(&claims.roles, required_roles) {
+        // Store Claims in the request extensions
+        req.extensions_mut().insert(claims);
+        req.extensions_mut().insert(source);
+        next.call(req).await
     } else {
-        Err(ErrorUnauthorized("Missing access token"))
+        Err(ErrorUnauthorized(format!(
+            "Access denied. Required roles: {:?}, but found: {:?}",
+            required_roles, claims.roles
+        )))
+    }
+}
+
+/// Picks the credential to verify, following `TOKEN_SOURCE_PRECEDENCE`.
+pub(crate) fn find_credential(req: &HttpRequest, config: &AppConfig) -> Option<Credential> {
+    if let Some(key) = req.headers().get(API_KEY_HEADER) {
+        return key
+            .to_str()
+            .ok()
+            .map(|key| Credential::ApiKey(key.to_string()));
+    }
+
+    config
+        .token_source_precedence
+        .iter()
+        .find_map(|source| match source {
+            TokenSource::Header => req
+                .headers()
+                .get(AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .map(|token| {
+                    if token.starts_with(API_KEY_PREFIX) {
+                        Credential::ApiKey(token.to_string())
+                    } else {
+                        Credential::Token(token.to_string(), AuthSource::Bearer)
+                    }
+                }),
+            TokenSource::Cookie => req
+                .cookie(ACCESS_TOKEN_COOKIE)
+                .map(|cookie| Credential::Token(cookie.value().to_string(), AuthSource::Cookie)),
+        })
+}
+
+/// Decodes and validates an access token, then checks it was not revoked and its
+/// user is still active.
+async fn verify_access_token(
+    keys: &KeyStore,
+    db_pool: &DatabasePool,
+    token: &str,
+) -> Result<Claims, Error> {
+    let claims = decode_access_token(keys, token)
+        .map_err(|err| ErrorUnauthorized(format!("Invalid or expired token: {}", err)))?;
+
+    match access_token_status(db_pool, &claims).await {
+        Ok(TokenStatus::Valid) => Ok(claims),
+        Ok(TokenStatus::Revoked) => Err(ErrorUnauthorized("Token has been revoked")),
+        Ok(TokenStatus::InactiveUser) => Err(ErrorUnauthorized("Account is not active")),
+        Err(err) => {
+            eprintln!("Failed to check token revocation: {:?}", err);
+            Err(ErrorInternalServerError("Failed to verify token"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{cookie::Cookie, test::TestRequest};
+
+    use super::*;
+
+    fn token_of(req: TestRequest, config: &AppConfig) -> Option<(String, AuthSource)> {
+        match find_credential(&req.to_http_request(), config) {
+            Some(Credential::Token(token, source)) => Some((token, source)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn finds_tokens_in_configured_order() {
+        let both = || {
+            TestRequest::post()
+                .insert_header((AUTHORIZATION, "Bearer header-jwt"))
+                .cookie(Cookie::new(ACCESS_TOKEN_COOKIE, "cookie-jwt"))
+        };
+        let cookie_first = AppConfig {
+            token_source_precedence: vec![TokenSource::Cookie, TokenSource::Header],
+            ..AppConfig::default()
+        };
+
+        assert_eq!(
+            token_of(both(), &AppConfig::default()),
+            Some(("header-jwt".to_string(), AuthSource::Bearer))
+        );
+        assert_eq!(
+            token_of(both(), &cookie_first),
+            Some(("cookie-jwt".to_string(), AuthSource::Cookie))
+        );
+        assert!(matches!(
+            find_credential(
+                &TestRequest::post()
+                    .insert_header((AUTHORIZATION, format!("Bearer {}abc", API_KEY_PREFIX)))
+                    .to_http_request(),
+                &AppConfig::default()
+            ),
+            Some(Credential::ApiKey(_))
+        ));
+        assert!(token_of(TestRequest::post(), &AppConfig::default()).is_none());
     }
 }
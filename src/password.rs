@@ -0,0 +1,318 @@
+use std::{fmt, str::FromStr, sync::Arc};
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordVerifier, SaltString},
+    Argon2, Params, Version,
+};
+use bcrypt::BcryptError;
+
+/// Cost range the `bcrypt` crate accepts; it does not export its own bounds.
+const BCRYPT_MIN_COST: u32 = 4;
+const BCRYPT_MAX_COST: u32 = 31;
+
+#[derive(Debug)]
+pub enum PasswordHashError {
+    Bcrypt(BcryptError),
+    Argon2(argon2::password_hash::Error),
+    /// The stored hash is in no supported format, e.g. the marker of a password-less account.
+    UnknownFormat,
+}
+
+impl fmt::Display for PasswordHashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PasswordHashError::Bcrypt(err) => write!(f, "bcrypt: {}", err),
+            PasswordHashError::Argon2(err) => write!(f, "argon2: {}", err),
+            PasswordHashError::UnknownFormat => write!(f, "unknown password hash format"),
+        }
+    }
+}
+
+impl std::error::Error for PasswordHashError {}
+
+impl From<BcryptError> for PasswordHashError {
+    fn from(err: BcryptError) -> Self {
+        PasswordHashError::Bcrypt(err)
+    }
+}
+
+impl From<argon2::password_hash::Error> for PasswordHashError {
+    fn from(err: argon2::password_hash::Error) -> Self {
+        PasswordHashError::Argon2(err)
+    }
+}
+
+/// Hashes passwords for storage and checks them at sign-in.
+///
+/// Hashes are verified whichever supported algorithm made them, so the configured
+/// algorithm or its parameters can change at any time: `needs_rehash` then tells
+/// which stored hashes to replace the next time their password is known.
+pub trait PasswordHasher: Send + Sync {
+    /// Hashes `password` with this hasher's algorithm and parameters.
+    fn hash(&self, password: &str) -> Result<String, PasswordHashError>;
+
+    /// Whether `hash` was made with another algorithm or other parameters.
+    fn needs_rehash(&self, hash: &str) -> bool;
+
+    /// Checks `password` against a hash made by any supported algorithm.
+    fn verify(&self, password: &str, hash: &str) -> Result<bool, PasswordHashError> {
+        verify_password(password, hash)
+    }
+}
+
+/// Hash of a random password, checked against at sign-in when the email is unknown or
+/// its hash is outdated, so those take as long as a real check. Made once at startup,
+/// so that no sign-in pays for making it.
+#[derive(Debug, Clone)]
+pub struct DummyPasswordHash(String);
+
+impl DummyPasswordHash {
+    pub fn new(hasher: &dyn PasswordHasher) -> Result<Self, PasswordHashError> {
+        hasher.hash(&crate::generate_uuid()).map(Self)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Argon2id, the algorithm recommended for new deployments.
+pub struct Argon2idHasher {
+    params: Params,
+}
+
+impl Argon2idHasher {
+    pub fn new(params: Params) -> Self {
+        Self { params }
+    }
+
+    fn argon2(&self) -> Argon2<'_> {
+        Argon2::new(
+            argon2::Algorithm::Argon2id,
+            Version::V0x13,
+            self.params.clone(),
+        )
+    }
+}
+
+impl PasswordHasher for Argon2idHasher {
+    fn hash(&self, password: &str) -> Result<String, PasswordHashError> {
+        let salt = SaltString::generate(&mut OsRng);
+        Ok(
+            argon2::PasswordHasher::hash_password(&self.argon2(), password.as_bytes(), &salt)?
+                .to_string(),
+        )
+    }
+
+    fn needs_rehash(&self, hash: &str) -> bool {
+        let Ok(parsed) = PasswordHash::new(hash) else {
+            return true;
+        };
+        let Ok(params) = Params::try_from(&parsed) else {
+            return true;
+        };
+        parsed.algorithm != argon2::ARGON2ID_IDENT
+            || parsed.version != Some(Version::V0x13.into())
+            || params.m_cost() != self.params.m_cost()
+            || params.t_cost() != self.params.t_cost()
+            || params.p_cost() != self.params.p_cost()
+    }
+}
+
+/// bcrypt, which every hash stored before Argon2id support uses.
+pub struct BcryptHasher {
+    cost: u32,
+}
+
+impl BcryptHasher {
+    pub fn new(cost: u32) -> Self {
+        Self { cost }
+    }
+}
+
+impl PasswordHasher for BcryptHasher {
+    fn hash(&self, password: &str) -> Result<String, PasswordHashError> {
+        Ok(bcrypt::hash(password, self.cost)?)
+    }
+
+    fn needs_rehash(&self, hash: &str) -> bool {
+        // `$2b$12$...`: the cost follows the version
+        detect_algorithm(hash) != Some(PasswordAlgorithm::Bcrypt)
+            || hash.get(4..6).and_then(|cost| cost.parse().ok()) != Some(self.cost)
+    }
+}
+
+/// Which algorithm new password hashes are made with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordAlgorithm {
+    Argon2id,
+    Bcrypt,
+}
+
+impl FromStr for PasswordAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "argon2id" => Ok(Self::Argon2id),
+            "bcrypt" => Ok(Self::Bcrypt),
+            other => Err(format!(
+                "Unknown password hash algorithm: {}, expected argon2id or bcrypt",
+                other
+            )),
+        }
+    }
+}
+
+/// Cost parameters of the password hash algorithms.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordHashParams {
+    pub argon2_memory_kib: u32,
+    pub argon2_iterations: u32,
+    pub argon2_parallelism: u32,
+    pub bcrypt_cost: u32,
+}
+
+impl Default for PasswordHashParams {
+    /// OWASP's minimum Argon2id settings, and bcrypt's default cost.
+    fn default() -> Self {
+        Self {
+            argon2_memory_kib: 19 * 1024,
+            argon2_iterations: 2,
+            argon2_parallelism: 1,
+            bcrypt_cost: bcrypt::DEFAULT_COST,
+        }
+    }
+}
+
+impl PasswordAlgorithm {
+    /// Builds the hasher for this algorithm, failing on out-of-range parameters.
+    pub fn build(&self, params: &PasswordHashParams) -> Result<Arc<dyn PasswordHasher>, String> {
+        Ok(match self {
+            Self::Argon2id => Arc::new(Argon2idHasher::new(
+                Params::new(
+                    params.argon2_memory_kib,
+                    params.argon2_iterations,
+                    params.argon2_parallelism,
+                    None,
+                )
+                .map_err(|err| format!("Invalid Argon2 parameters: {}", err))?,
+            )),
+            Self::Bcrypt => {
+                if !(BCRYPT_MIN_COST..=BCRYPT_MAX_COST).contains(&params.bcrypt_cost) {
+                    return Err(format!(
+                        "bcrypt cost must be between {} and {}",
+                        BCRYPT_MIN_COST, BCRYPT_MAX_COST
+                    ));
+                }
+                Arc::new(BcryptHasher::new(params.bcrypt_cost))
+            }
+        })
+    }
+}
+
+/// Tells the algorithm of a stored hash from its prefix.
+pub fn detect_algorithm(hash: &str) -> Option<PasswordAlgorithm> {
+    if hash.starts_with("$argon2id$") {
+        Some(PasswordAlgorithm::Argon2id)
+    } else if ["$2a$", "$2b$", "$2x$", "$2y$"]
+        .iter()
+        .any(|prefix| hash.starts_with(prefix))
+    {
+        Some(PasswordAlgorithm::Bcrypt)
+    } else {
+        None
+    }
+}
+
+/// Checks `password` against a hash made by any supported algorithm.
+pub fn verify_password(password: &str, hash: &str) -> Result<bool, PasswordHashError> {
+    match detect_algorithm(hash) {
+        Some(PasswordAlgorithm::Argon2id) => {
+            let parsed = PasswordHash::new(hash)?;
+            // The algorithm, version and parameters are taken from the hash
+            match Argon2::default().verify_password(password.as_bytes(), &parsed) {
+                Ok(()) => Ok(true),
+                Err(argon2::password_hash::Error::Password) => Ok(false),
+                Err(err) => Err(err.into()),
+            }
+        }
+        Some(PasswordAlgorithm::Bcrypt) => Ok(bcrypt::verify(password, hash)?),
+        None => Err(PasswordHashError::UnknownFormat),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cheap parameters, so the tests run quickly.
+    fn params() -> PasswordHashParams {
+        PasswordHashParams {
+            argon2_memory_kib: 64,
+            argon2_iterations: 1,
+            argon2_parallelism: 1,
+            bcrypt_cost: 4,
+        }
+    }
+
+    #[test]
+    fn dummy_hash_uses_the_current_algorithm_and_parameters() {
+        for algorithm in [PasswordAlgorithm::Argon2id, PasswordAlgorithm::Bcrypt] {
+            let hasher = algorithm.build(&params()).unwrap();
+            let dummy = DummyPasswordHash::new(hasher.as_ref()).unwrap();
+
+            assert!(!hasher.needs_rehash(dummy.as_str()));
+            assert!(!hasher.verify("password", dummy.as_str()).unwrap());
+        }
+    }
+
+    #[test]
+    fn verifies_hashes_of_either_algorithm() {
+        let argon2 = PasswordAlgorithm::Argon2id.build(&params()).unwrap();
+        let bcrypt = PasswordAlgorithm::Bcrypt.build(&params()).unwrap();
+        let legacy = bcrypt.hash("correct horse").unwrap();
+        let current = argon2.hash("correct horse").unwrap();
+
+        assert_eq!(
+            detect_algorithm(&current),
+            Some(PasswordAlgorithm::Argon2id)
+        );
+        assert!(argon2.verify("correct horse", &legacy).unwrap());
+        assert!(bcrypt.verify("correct horse", &current).unwrap());
+        assert!(!argon2.verify("wrong horse", &legacy).unwrap());
+        assert!(!argon2.verify("wrong horse", &current).unwrap());
+        assert!(argon2.verify("correct horse", "!").is_err());
+    }
+
+    #[test]
+    fn outdated_hashes_need_rehash() {
+        let argon2 = PasswordAlgorithm::Argon2id.build(&params()).unwrap();
+        let stronger = PasswordAlgorithm::Argon2id
+            .build(&PasswordHashParams {
+                argon2_iterations: 2,
+                ..params()
+            })
+            .unwrap();
+        let bcrypt = PasswordAlgorithm::Bcrypt.build(&params()).unwrap();
+        let current = argon2.hash("correct horse").unwrap();
+        let legacy = bcrypt.hash("correct horse").unwrap();
+
+        assert!(!argon2.needs_rehash(&current));
+        assert!(argon2.needs_rehash(&legacy));
+        assert!(stronger.needs_rehash(&current));
+        assert!(!bcrypt.needs_rehash(&legacy));
+        assert!(bcrypt.needs_rehash(&current));
+    }
+
+    #[test]
+    fn rejects_unknown_algorithms_and_bad_parameters() {
+        assert_eq!("Argon2id".parse(), Ok(PasswordAlgorithm::Argon2id));
+        assert!("md5".parse::<PasswordAlgorithm>().is_err());
+        let no_memory = PasswordHashParams {
+            argon2_memory_kib: 0,
+            ..params()
+        };
+        assert!(PasswordAlgorithm::Argon2id.build(&no_memory).is_err());
+    }
+}
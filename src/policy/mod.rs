@@ -0,0 +1,299 @@
+use std::{collections::HashMap, str::FromStr};
+
+use actix_web::{web::Data, HttpMessage, HttpRequest, HttpResponse};
+
+use crate::{
+    config::AppConfig,
+    schema::auth::{Claims, RoleHierarchy},
+};
+
+/// A single capability granted to a role, e.g. `url:read:any`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Permission {
+    UrlCreate,
+    UrlRead,
+    UrlReadAny,
+    UrlUpdate,
+    UrlUpdateAny,
+    UrlDelete,
+    UrlDeleteAny,
+    UserManage,
+    ApiKeyManage,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 9] = [
+        Permission::UrlCreate,
+        Permission::UrlRead,
+        Permission::UrlReadAny,
+        Permission::UrlUpdate,
+        Permission::UrlUpdateAny,
+        Permission::UrlDelete,
+        Permission::UrlDeleteAny,
+        Permission::UserManage,
+        Permission::ApiKeyManage,
+    ];
+
+    /// The name used for this permission in API key scopes.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::UrlCreate => "url:create",
+            Permission::UrlRead => "url:read",
+            Permission::UrlReadAny => "url:read:any",
+            Permission::UrlUpdate => "url:update",
+            Permission::UrlUpdateAny => "url:update:any",
+            Permission::UrlDelete => "url:delete",
+            Permission::UrlDeleteAny => "url:delete:any",
+            Permission::UserManage => "user:manage",
+            Permission::ApiKeyManage => "api_key:manage",
+        }
+    }
+}
+
+impl FromStr for Permission {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Permission::ALL
+            .into_iter()
+            .find(|permission| permission.as_str() == s)
+            .ok_or_else(|| format!("Unknown scope `{}`", s))
+    }
+}
+
+/// What the caller wants to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Create,
+    Read,
+    Update,
+    Delete,
+}
+
+/// What the action applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource<'a> {
+    /// A short URL; `owner` is its `user_id`, if any. Use `None` when creating one.
+    Url { owner: Option<&'a str> },
+    /// Any user account.
+    User,
+    /// The caller's own API keys.
+    ApiKey,
+}
+
+/// Maps roles to permissions and decides who may do what.
+///
+/// Ownership rules live here: the `url:*` permissions cover the caller's own
+/// links, the `url:*:any` permissions cover everyone's. Requests made with a
+/// scoped API key only get the permissions that are both granted by the roles
+/// and listed in the key's scopes.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    hierarchy: RoleHierarchy,
+    role_permissions: HashMap<String, Vec<Permission>>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self::new(RoleHierarchy::default())
+    }
+}
+
+impl Policy {
+    /// Builds the policy with the built-in role mapping: `user` manages its own
+    /// links and API keys, `admin` manages every link and every user.
+    pub fn new(hierarchy: RoleHierarchy) -> Self {
+        let role_permissions = HashMap::from([
+            (
+                "user".to_string(),
+                vec![
+                    Permission::UrlCreate,
+                    Permission::UrlRead,
+                    Permission::UrlUpdate,
+                    Permission::UrlDelete,
+                    Permission::ApiKeyManage,
+                ],
+            ),
+            (
+                "admin".to_string(),
+                vec![
+                    Permission::UrlReadAny,
+                    Permission::UrlUpdateAny,
+                    Permission::UrlDeleteAny,
+                    Permission::UserManage,
+                ],
+            ),
+        ]);
+
+        Self {
+            hierarchy,
+            role_permissions,
+        }
+    }
+
+    /// Checks whether `roles` satisfy at least one of `required`, through the hierarchy.
+    pub fn grants_any_role(&self, roles: &[String], required: &[&str]) -> bool {
+        self.hierarchy.grants_any(roles, required)
+    }
+
+    /// Checks whether the caller's roles, and scopes if any, grant `permission`.
+    pub fn has_permission(&self, claims: &Claims, permission: Permission) -> bool {
+        if let Some(scopes) = &claims.scopes {
+            if !scopes.iter().any(|scope| scope == permission.as_str()) {
+                return false;
+            }
+        }
+
+        self.hierarchy.expand(&claims.roles).iter().any(|role| {
+            self.role_permissions
+                .get(*role)
+                .is_some_and(|permissions| permissions.contains(&permission))
+        })
+    }
+
+    /// Decides whether the caller may perform `action` on `resource`.
+    pub fn can(&self, claims: &Claims, action: Action, resource: Resource) -> bool {
+        match resource {
+            Resource::User => self.has_permission(claims, Permission::UserManage),
+            Resource::ApiKey => self.has_permission(claims, Permission::ApiKeyManage),
+            Resource::Url { owner } => {
+                let (own, any) = match action {
+                    Action::Create => return self.has_permission(claims, Permission::UrlCreate),
+                    Action::Read => (Permission::UrlRead, Permission::UrlReadAny),
+                    Action::Update => (Permission::UrlUpdate, Permission::UrlUpdateAny),
+                    Action::Delete => (Permission::UrlDelete, Permission::UrlDeleteAny),
+                };
+                let is_owner = owner == Some(claims.sub.as_str());
+                (is_owner && self.has_permission(claims, own)) || self.has_permission(claims, any)
+            }
+        }
+    }
+}
+
+/// Fetches the caller's claims and checks them against the policy, producing the
+/// error response (401 without claims, 403 when denied) for the handler to return.
+pub fn authorize(
+    req: &HttpRequest,
+    action: Action,
+    resource: Resource,
+) -> Result<Claims, HttpResponse> {
+    let claims = req
+        .extensions()
+        .get::<Claims>()
+        .cloned()
+        .ok_or_else(|| HttpResponse::Unauthorized().json("Unauthorized"))?;
+    let config = req
+        .app_data::<Data<AppConfig>>()
+        .expect("AppConfig must be registered as app data");
+
+    if config.policy.can(&claims, action, resource) {
+        Ok(claims)
+    } else {
+        Err(HttpResponse::Forbidden().json("You are not allowed to perform this action"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(sub: &str, roles: &[&str]) -> Claims {
+        Claims::new(
+            sub.to_string(),
+            roles.iter().map(|r| r.to_string()).collect(),
+            chrono::Duration::minutes(15),
+        )
+    }
+
+    #[test]
+    fn user_manages_own_urls_only() {
+        let policy = Policy::default();
+        let user = claims("alice", &["user"]);
+        let own = Resource::Url {
+            owner: Some("alice"),
+        };
+        let other = Resource::Url { owner: Some("bob") };
+
+        assert!(policy.can(&user, Action::Create, Resource::Url { owner: None }));
+        for action in [Action::Read, Action::Update, Action::Delete] {
+            assert!(policy.can(&user, action, own));
+            assert!(!policy.can(&user, action, other));
+        }
+    }
+
+    #[test]
+    fn ownerless_urls_need_any_permission() {
+        let policy = Policy::default();
+        let orphan = Resource::Url { owner: None };
+
+        assert!(!policy.can(&claims("alice", &["user"]), Action::Read, orphan));
+        assert!(policy.can(&claims("root", &["admin"]), Action::Read, orphan));
+    }
+
+    #[test]
+    fn admin_overrides_ownership_and_inherits_user() {
+        let policy = Policy::default();
+        let admin = claims("root", &["admin"]);
+
+        assert!(policy.can(&admin, Action::Create, Resource::Url { owner: None }));
+        for action in [Action::Read, Action::Update, Action::Delete] {
+            assert!(policy.can(&admin, action, Resource::Url { owner: Some("bob") }));
+        }
+    }
+
+    #[test]
+    fn only_admin_manages_users() {
+        let policy = Policy::default();
+
+        assert!(!policy.can(&claims("alice", &["user"]), Action::Delete, Resource::User));
+        assert!(policy.can(&claims("root", &["admin"]), Action::Delete, Resource::User));
+    }
+
+    #[test]
+    fn without_hierarchy_admin_does_not_inherit_user() {
+        let policy = Policy::new(RoleHierarchy::parse("").unwrap());
+        let admin = claims("root", &["admin"]);
+
+        assert!(!policy.can(&admin, Action::Create, Resource::Url { owner: None }));
+        assert!(policy.can(&admin, Action::Update, Resource::Url { owner: Some("bob") }));
+    }
+
+    #[test]
+    fn api_key_scopes_narrow_role_permissions() {
+        let policy = Policy::default();
+        let mut key = claims("alice", &["user"]);
+        key.scopes = Some(vec!["url:read".to_string(), "user:manage".to_string()]);
+        let own = Resource::Url {
+            owner: Some("alice"),
+        };
+
+        assert!(policy.can(&key, Action::Read, own));
+        assert!(!policy.can(&key, Action::Create, Resource::Url { owner: None }));
+        assert!(!policy.can(&key, Action::Create, Resource::ApiKey));
+        // A scope never grants more than the roles do
+        assert!(!policy.can(&key, Action::Delete, Resource::User));
+    }
+
+    #[test]
+    fn scope_names_round_trip() {
+        for permission in Permission::ALL {
+            assert_eq!(permission.as_str().parse(), Ok(permission));
+        }
+        assert!("url:*".parse::<Permission>().is_err());
+    }
+
+    #[test]
+    fn unknown_roles_grant_nothing() {
+        let policy = Policy::default();
+        let guest = claims("guest", &["guest"]);
+
+        assert!(!policy.can(&guest, Action::Create, Resource::Url { owner: None }));
+        assert!(!policy.can(
+            &guest,
+            Action::Read,
+            Resource::Url {
+                owner: Some("guest")
+            }
+        ));
+    }
+}
@@ -0,0 +1,223 @@
+use std::net::IpAddr;
+
+use actix_url_shortener::client_ip::TrustedProxies;
+use actix_web::{http::header, HttpRequest};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use woothee::parser::Parser;
+
+/// A single redirect, as stored in `click_events`.
+#[derive(Debug, Clone)]
+pub struct ClickEvent {
+    pub short_url_id: String,
+    pub clicked_at: DateTime<Utc>,
+    pub referrer: Option<String>,
+    pub user_agent: Option<String>,
+    pub accept_language: Option<String>,
+    pub ip_address: Option<String>,
+    pub browser: Option<String>,
+    pub os: Option<String>,
+    pub device_class: Option<String>,
+    pub language: Option<String>,
+}
+
+impl ClickEvent {
+    /// Captures the click details from an incoming redirect request.
+    pub fn from_request(
+        req: &HttpRequest,
+        short_url_id: &str,
+        trusted_proxies: &TrustedProxies,
+    ) -> Self {
+        let header_value = |name: header::HeaderName| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+
+        let user_agent = header_value(header::USER_AGENT);
+        let accept_language = header_value(header::ACCEPT_LANGUAGE);
+        let ip_address = trusted_proxies
+            .client_ip_of(req)
+            .map(|ip| anonymize_ip(ip).to_string());
+
+        let (browser, os, device_class) = match user_agent.as_deref().and_then(parse_user_agent) {
+            Some((browser, os, device_class)) => (Some(browser), Some(os), Some(device_class)),
+            None => (None, None, None),
+        };
+
+        Self {
+            short_url_id: short_url_id.to_string(),
+            clicked_at: Utc::now(),
+            referrer: header_value(header::REFERER),
+            language: accept_language.as_deref().and_then(primary_language),
+            user_agent,
+            accept_language,
+            ip_address,
+            browser,
+            os,
+            device_class,
+        }
+    }
+}
+
+/// Drops the host part of an address: the last octet for IPv4, all but the /48 for IPv6.
+pub fn anonymize_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            IpAddr::from([a, b, c, 0])
+        }
+        IpAddr::V6(v6) => {
+            let mut segments = v6.segments();
+            segments[3..].fill(0);
+            IpAddr::from(segments)
+        }
+    }
+}
+
+/// Splits a user agent into browser, OS and device class (`desktop`, `mobile`, `bot`, `other`).
+pub fn parse_user_agent(user_agent: &str) -> Option<(String, String, String)> {
+    let result = Parser::new().parse(user_agent)?;
+    let device_class = match result.category {
+        "pc" => "desktop",
+        "smartphone" | "mobilephone" => "mobile",
+        "crawler" => "bot",
+        _ => "other",
+    };
+    Some((
+        result.name.to_string(),
+        result.os.to_string(),
+        device_class.to_string(),
+    ))
+}
+
+/// Returns the first language tag of an `Accept-Language` header, e.g. `en-US`.
+fn primary_language(accept_language: &str) -> Option<String> {
+    accept_language
+        .split(',')
+        .next()
+        .and_then(|tag| tag.split(';').next())
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty() && *tag != "*")
+        .map(str::to_string)
+}
+
+/// Granularity of the click histogram.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+    Hour,
+    #[default]
+    Day,
+    Week,
+}
+
+impl Bucket {
+    /// MySQL expression mapping `clicked_at` to the start of its bucket.
+    pub fn sql_expression(self) -> &'static str {
+        match self {
+            Bucket::Hour => "DATE_FORMAT(clicked_at, '%Y-%m-%d %H:00:00')",
+            Bucket::Day => "DATE_FORMAT(clicked_at, '%Y-%m-%d')",
+            Bucket::Week => {
+                "DATE_FORMAT(DATE_SUB(clicked_at, INTERVAL WEEKDAY(clicked_at) DAY), '%Y-%m-%d')"
+            }
+        }
+    }
+}
+
+/// Query string of `GET /urls/{id}/analytics`. Defaults to the last 7 days, by day.
+#[derive(Debug, Deserialize)]
+pub struct AnalyticsQuery {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub bucket: Bucket,
+}
+
+/// Number of clicks for one bucket or one value of a dimension.
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct ClickCount {
+    pub value: String,
+    pub clicks: i64,
+}
+
+/// Aggregated clicks of a short URL over a date range.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UrlAnalytics {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub total_clicks: i64,
+    pub clicks: Vec<ClickCount>,
+    pub top_referrers: Vec<ClickCount>,
+    pub top_browsers: Vec<ClickCount>,
+    pub top_oses: Vec<ClickCount>,
+    pub top_languages: Vec<ClickCount>,
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    const FIREFOX_ON_WINDOWS: &str =
+        "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:121.0) Gecko/20100101 Firefox/121.0";
+    const SAFARI_ON_IPHONE: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_2 like Mac OS X) \
+        AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.2 Mobile/15E148 Safari/604.1";
+
+    #[test]
+    fn anonymizes_ipv4_and_ipv6() {
+        assert_eq!(
+            anonymize_ip("203.0.113.42".parse().unwrap()),
+            "203.0.113.0".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            anonymize_ip("2001:db8:abcd:12:34::1".parse().unwrap()),
+            "2001:db8:abcd::".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn classifies_user_agents() {
+        let (browser, os, device_class) = parse_user_agent(FIREFOX_ON_WINDOWS).unwrap();
+        assert_eq!(browser, "Firefox");
+        assert_eq!(os, "Windows 10");
+        assert_eq!(device_class, "desktop");
+
+        let (browser, _, device_class) = parse_user_agent(SAFARI_ON_IPHONE).unwrap();
+        assert_eq!(browser, "Safari");
+        assert_eq!(device_class, "mobile");
+
+        let (_, _, device_class) = parse_user_agent("Googlebot/2.1").unwrap();
+        assert_eq!(device_class, "bot");
+    }
+
+    #[test]
+    fn picks_the_first_language() {
+        assert_eq!(
+            primary_language("en-US,en;q=0.9,de;q=0.8").as_deref(),
+            Some("en-US")
+        );
+        assert_eq!(primary_language(" fr;q=0.7 ").as_deref(), Some("fr"));
+        assert_eq!(primary_language("*"), None);
+        assert_eq!(primary_language(""), None);
+    }
+
+    #[test]
+    fn ignores_forwarded_for_from_untrusted_peers() {
+        let req = TestRequest::default()
+            .peer_addr("198.51.100.7:5000".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "203.0.113.9"))
+            .to_http_request();
+
+        let event = ClickEvent::from_request(&req, "id", &TrustedProxies::default());
+        assert_eq!(event.ip_address.as_deref(), Some("198.51.100.0"));
+
+        let proxies = TrustedProxies::parse("198.51.100.7").unwrap();
+        let event = ClickEvent::from_request(&req, "id", &proxies);
+        assert_eq!(event.ip_address.as_deref(), Some("203.0.113.0"));
+    }
+}
@@ -0,0 +1,51 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, types::Json};
+
+/// Every API key starts with this, so it can be told apart from a JWT.
+pub const API_KEY_PREFIX: &str = "usk_";
+
+/// How much of a key is kept in clear text to help users recognize it.
+pub const API_KEY_DISPLAY_LENGTH: usize = 12;
+
+/// A personal API key, as shown to its owner. The key itself is never returned again.
+#[derive(Debug, Clone, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub key_prefix: String, // First characters of the key, e.g. `usk_1a2b3c4d`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Json<Vec<String>>>, // `None` grants everything the user's roles grant
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    pub scopes: Option<Vec<String>>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenameApiKeyRequest {
+    pub name: String,
+}
+
+/// The key row joined with its owner, as needed to authenticate a request.
+#[derive(Debug, FromRow)]
+pub struct ApiKeyCredentials {
+    pub id: String,
+    pub user_id: String,
+    pub scopes: Option<Json<Vec<String>>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub roles: Json<Vec<String>>,
+}
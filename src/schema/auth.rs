@@ -1,23 +1,186 @@
+use std::collections::{HashMap, HashSet};
+
+use actix_url_shortener::generate_uuid;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
 #[derive(Debug, Deserialize)]
 pub struct LoginRequest {
     pub email: String,
     pub password: String,
 }
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+#[derive(Debug, Deserialize)]
+pub struct ResendVerificationRequest {
+    pub email: String,
+}
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub new_password: String,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
-    pub sub: String,   // Cow can be &str or String
-    pub roles: String, // Cow can be &str or String
+    pub sub: String,        // Cow can be &str or String
+    pub roles: Vec<String>, // Every role of the user, as stored in `users.roles`
     pub exp: usize,
+    pub iat: usize,
+    #[serde(default)]
+    pub iat_ms: i64, // `iat` in milliseconds, compared against `users.tokens_invalid_before`
+    pub jti: String, // Unique token id, recorded in `revoked_tokens` on sign-out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<String>>, // Set for API keys; narrows what the roles grant
 }
 
 impl Claims {
-    pub fn new(sub: String, roles: String) -> Self {
-        let exp_time = chrono::Utc::now().timestamp() + 3600; // Token valid for 1 hour
+    pub fn new(sub: String, roles: Vec<String>, ttl: chrono::Duration) -> Self {
+        let now = chrono::Utc::now();
+        let exp_time = (now + ttl).timestamp(); // Token valid for `ttl`
         Self {
             exp: exp_time as usize,
+            iat: now.timestamp() as usize,
+            iat_ms: now.timestamp_millis(),
+            jti: generate_uuid(),
+            scopes: None,
             roles, // Borrow the roles as Cow<'a, str>
             sub,   // Borrow the sub as Cow<'a, str>
         }
     }
+
+    /// When the token was issued; tokens from before `iat_ms` existed only have seconds.
+    pub fn issued_at(&self) -> DateTime<Utc> {
+        let millis = match self.iat_ms {
+            0 => self.iat as i64 * 1000,
+            millis => millis,
+        };
+        DateTime::from_timestamp_millis(millis).unwrap_or(DateTime::UNIX_EPOCH)
+    }
+
+    /// Claims for a request authenticated with an API key; `jti` is the key's id.
+    pub fn for_api_key(
+        sub: String,
+        roles: Vec<String>,
+        key_id: String,
+        expires_at: Option<DateTime<Utc>>,
+        scopes: Option<Vec<String>>,
+    ) -> Self {
+        let now = Utc::now();
+        Self {
+            sub,
+            roles,
+            exp: expires_at.map_or(usize::MAX, |at| at.timestamp() as usize),
+            iat: now.timestamp() as usize,
+            iat_ms: now.timestamp_millis(),
+            jti: key_id,
+            scopes,
+        }
+    }
+}
+
+/// What a mailed, single-use token in `user_tokens` may be used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenPurpose {
+    PasswordReset,
+    EmailVerification,
+}
+
+impl TokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenPurpose::PasswordReset => "password_reset",
+            TokenPurpose::EmailVerification => "email_verification",
+        }
+    }
+}
+
+/// The parts of a stored refresh token needed to rotate it.
+#[derive(Debug, Clone, FromRow)]
+pub struct RefreshToken {
+    pub id: String,
+    pub user_id: String,
+    /// Every token rotated from the same login shares a family.
+    pub family_id: String,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// Which roles imply which: with `admin ⊇ user`, an admin passes every check that requires `user`.
+#[derive(Debug, Clone)]
+pub struct RoleHierarchy {
+    implied: HashMap<String, Vec<String>>,
+}
+
+impl Default for RoleHierarchy {
+    fn default() -> Self {
+        Self::parse("admin>user").expect("default role hierarchy is valid")
+    }
+}
+
+impl RoleHierarchy {
+    /// Parses comma-separated `parent>child` pairs, e.g. `admin>user,user>guest`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut implied: HashMap<String, Vec<String>> = HashMap::new();
+        for pair in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+        {
+            let (parent, child) = pair
+                .split_once('>')
+                .map(|(parent, child)| (parent.trim(), child.trim()))
+                .filter(|(parent, child)| !parent.is_empty() && !child.is_empty())
+                .ok_or_else(|| format!("Invalid role hierarchy entry: {}", pair))?;
+            implied
+                .entry(parent.to_string())
+                .or_default()
+                .push(child.to_string());
+        }
+        Ok(Self { implied })
+    }
+
+    /// Returns the given roles plus every role they imply, transitively.
+    pub fn expand<'a>(&'a self, roles: &'a [String]) -> HashSet<&'a str> {
+        let mut effective = HashSet::new();
+        let mut pending: Vec<&str> = roles.iter().map(String::as_str).collect();
+        while let Some(role) = pending.pop() {
+            if effective.insert(role) {
+                if let Some(children) = self.implied.get(role) {
+                    pending.extend(children.iter().map(String::as_str));
+                }
+            }
+        }
+        effective
+    }
+
+    /// Checks whether `roles` satisfy at least one of `required`.
+    pub fn grants_any(&self, roles: &[String], required: &[&str]) -> bool {
+        let effective = self.expand(roles);
+        required.iter().any(|role| effective.contains(role))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn issued_at_keeps_milliseconds() {
+        let mut claims = Claims::new("user".to_string(), vec![], chrono::Duration::minutes(5));
+        assert_eq!(claims.issued_at().timestamp_millis(), claims.iat_ms);
+        assert_eq!(claims.issued_at().timestamp(), claims.iat as i64);
+
+        // Tokens issued before `iat_ms` was added
+        claims.iat_ms = 0;
+        assert_eq!(
+            claims.issued_at().timestamp_millis(),
+            claims.iat as i64 * 1000
+        );
+    }
 }
@@ -0,0 +1,52 @@
+use actix_url_shortener::generate_uuid;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+/// `aud` of the token handed out after the password step of a two-factor sign-in.
+/// Access tokens carry no `aud`, so the two cannot be used in place of each other.
+pub const MFA_PENDING_AUDIENCE: &str = "mfa_pending";
+
+/// Claims of the short-lived token that stands for "password checked, code pending".
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MfaPendingClaims {
+    pub sub: String,
+    pub aud: String,
+    pub exp: usize,
+    pub iat: usize,
+    pub jti: String,
+}
+
+impl MfaPendingClaims {
+    pub fn new(sub: String, ttl: chrono::Duration) -> Self {
+        let now = Utc::now();
+        Self {
+            sub,
+            aud: MFA_PENDING_AUDIENCE.to_string(),
+            exp: (now + ttl).timestamp() as usize,
+            iat: now.timestamp() as usize,
+            jti: generate_uuid(),
+        }
+    }
+}
+
+/// Returned when enrollment starts; the secret is shown once, for manual entry.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MfaEnrollment {
+    pub secret: String,
+    pub otpauth_uri: String, // `otpauth://totp/...`, what the QR code encodes
+    pub qr_code_svg: String,
+}
+
+/// A code from the authenticator app, or a recovery code where accepted.
+#[derive(Debug, Deserialize)]
+pub struct MfaCodeRequest {
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyMfaRequest {
+    pub mfa_token: String,
+    pub code: String,
+}
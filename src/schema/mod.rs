@@ -1,3 +1,7 @@
+pub mod analytics;
+pub mod api_key;
 pub mod auth;
+pub mod mfa;
+pub mod oidc;
 pub mod url;
 pub mod user;
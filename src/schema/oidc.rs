@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+/// `aud` of the cookie carrying a sign-in's state between `/auth/oidc/login` and the callback.
+pub const OIDC_LOGIN_AUDIENCE: &str = "oidc_login";
+
+/// What the callback needs to finish a sign-in started in this browser. Kept in a
+/// signed cookie, which also ties the callback to the browser that started it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcLoginClaims {
+    pub aud: String,
+    pub exp: usize,
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+/// Query of the provider's redirect back to `/auth/oidc/callback`.
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
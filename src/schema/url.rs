@@ -3,6 +3,44 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
 
+/// HTTP status used when redirecting a short URL to its destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(try_from = "u16", into = "u16")]
+#[repr(u16)]
+pub enum RedirectType {
+    /// 301, cached by browsers and search engines; the method may change to GET.
+    MovedPermanently = 301,
+    /// 302, the historical default.
+    Found = 302,
+    /// 307, preserves the method and body.
+    TemporaryRedirect = 307,
+    /// 308, permanent and preserves the method and body.
+    PermanentRedirect = 308,
+}
+
+impl TryFrom<u16> for RedirectType {
+    type Error = String;
+
+    fn try_from(status: u16) -> Result<Self, Self::Error> {
+        match status {
+            301 => Ok(Self::MovedPermanently),
+            302 => Ok(Self::Found),
+            307 => Ok(Self::TemporaryRedirect),
+            308 => Ok(Self::PermanentRedirect),
+            other => Err(format!(
+                "Unsupported redirect type {}, expected 301, 302, 307 or 308",
+                other
+            )),
+        }
+    }
+}
+
+impl From<RedirectType> for u16 {
+    fn from(redirect_type: RedirectType) -> Self {
+        redirect_type as u16
+    }
+}
+
 /// Represents a shortened URL and its metadata.
 #[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
 pub struct ShortUrl {
@@ -18,8 +56,14 @@ pub struct ShortUrl {
     #[serde(skip_serializing_if = "Option::is_none")] // Don't serialize if it's None
     pub expiration: Option<DateTime<Utc>>,
 
+    #[serde(skip_serializing_if = "Option::is_none")] // Don't serialize if it's None
+    pub expired_redirect_url: Option<String>, // Where to send visitors once the link has expired
+
     pub click_count: u64,
 
+    #[serde(skip_serializing_if = "Option::is_none")] // Don't serialize if it's None
+    pub redirect_type: Option<RedirectType>, // Falls back to the server-wide default when unset
+
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")] // Don't serialize if it's None
     pub user_id: Option<String>, // Added user_id to the struct
@@ -33,7 +77,9 @@ impl Default for ShortUrl {
             short_code: String::new(),
             created_at: Utc::now(),
             expiration: None,
+            expired_redirect_url: None,
             click_count: 0,
+            redirect_type: None,
             user_id: None, // Added user_id to the default implementation
         }
     }
@@ -51,10 +97,38 @@ impl ShortUrl {
 pub struct CreateUrlRequest {
     #[serde(rename = "originalUrl")]
     pub original_url: String,
+
+    pub expiration: Option<DateTime<Utc>>,
+
+    #[serde(rename = "expiredRedirectUrl")]
+    pub expired_redirect_url: Option<String>,
+
+    /// Custom short code chosen by the user instead of a generated one.
+    pub alias: Option<String>,
+
+    #[serde(rename = "redirectType")]
+    pub redirect_type: Option<RedirectType>,
 }
 
 #[derive(Deserialize)]
 pub struct UpdateUrlRequest {
     pub original_url: Option<String>,
     pub expiration: Option<DateTime<Utc>>,
+    pub expired_redirect_url: Option<String>,
+    pub redirect_type: Option<RedirectType>,
+
+    /// Issue a new short code; the old one keeps redirecting for a grace period.
+    #[serde(default)]
+    pub regenerate_code: bool,
+}
+
+impl UpdateUrlRequest {
+    /// Whether the request asks for no change at all.
+    pub fn is_empty(&self) -> bool {
+        self.original_url.is_none()
+            && self.expiration.is_none()
+            && self.expired_redirect_url.is_none()
+            && self.redirect_type.is_none()
+            && !self.regenerate_code
+    }
 }
@@ -1,4 +1,8 @@
-use actix_url_shortener::{generate_password_hash, generate_uuid};
+use actix_url_shortener::{
+    generate_uuid,
+    password::{PasswordHashError, PasswordHasher},
+    validation::{validate_email, validate_username, PasswordPolicy, ValidationErrors},
+};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{prelude::FromRow, types::Json};
@@ -37,9 +41,13 @@ impl Default for User {
     }
 }
 impl User {
-    /// Hashes a password using bcrypt and updates the `password` field.
-    pub fn set_password(&mut self, plain_password: &str) -> Result<&mut Self, bcrypt::BcryptError> {
-        let hashed_password = generate_password_hash(plain_password)?;
+    /// Hashes a password with the configured hasher and updates the `password` field.
+    pub fn set_password(
+        &mut self,
+        hasher: &dyn PasswordHasher,
+        plain_password: &str,
+    ) -> Result<&mut Self, PasswordHashError> {
+        let hashed_password = hasher.hash(plain_password)?;
         self.password = hashed_password;
         Ok(self) // Return a mutable reference to the instance
     }
@@ -66,3 +74,41 @@ pub struct UpdateUserRequest {
     pub email: Option<String>,
     pub password: Option<String>,
 }
+
+impl CreateUserRequest {
+    /// Checks every field, collecting all problems.
+    pub fn validate(&self, policy: &PasswordPolicy) -> ValidationErrors {
+        let mut errors = ValidationErrors::default();
+        if let Err(message) = validate_username(&self.username) {
+            errors.add("username", message);
+        }
+        if let Err(message) = validate_email(&self.email) {
+            errors.add("email", message);
+        }
+        policy.validate_field(&mut errors, "password", &self.password, &self.username);
+        errors
+    }
+}
+
+impl UpdateUserRequest {
+    /// Checks the fields being changed. A new password is checked against the new
+    /// username, or `current_username` when it is kept.
+    pub fn validate(&self, policy: &PasswordPolicy, current_username: &str) -> ValidationErrors {
+        let mut errors = ValidationErrors::default();
+        if let Some(username) = &self.username {
+            if let Err(message) = validate_username(username) {
+                errors.add("username", message);
+            }
+        }
+        if let Some(email) = &self.email {
+            if let Err(message) = validate_email(email) {
+                errors.add("email", message);
+            }
+        }
+        if let Some(password) = &self.password {
+            let username = self.username.as_deref().unwrap_or(current_username);
+            policy.validate_field(&mut errors, "password", password, username);
+        }
+        errors
+    }
+}
@@ -0,0 +1,156 @@
+use actix_web::{
+    get,
+    web::{Data, Path, Query},
+    HttpRequest, HttpResponse, Responder,
+};
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{MySql, MySqlConnection, QueryBuilder};
+
+use crate::{
+    database::DatabasePool,
+    policy::{authorize, Action, Resource},
+    schema::{
+        analytics::{AnalyticsQuery, ClickCount, ClickEvent, UrlAnalytics},
+        url::ShortUrl,
+    },
+};
+
+/// How many entries the "top" lists return.
+const TOP_LIMIT: i64 = 10;
+
+/// Most events written by one insert. Each takes 10 placeholders, and MySQL allows
+/// 65,535 per statement.
+const EVENTS_PER_INSERT: usize = 1000;
+
+/// Stores a batch of click events with multi-row inserts of up to `EVENTS_PER_INSERT` rows.
+///
+/// `INSERT IGNORE` skips events whose link was deleted before the batch was written.
+pub async fn record_click_events(
+    conn: &mut MySqlConnection,
+    events: &[ClickEvent],
+) -> Result<(), sqlx::Error> {
+    for chunk in events.chunks(EVENTS_PER_INSERT) {
+        let mut query = QueryBuilder::<MySql>::new(
+            "INSERT IGNORE INTO click_events \
+             (short_url_id, clicked_at, referrer, user_agent, accept_language, ip_address, browser, os, device_class, language) ",
+        );
+        query.push_values(chunk, |mut row, event| {
+            row.push_bind(&event.short_url_id)
+                .push_bind(event.clicked_at)
+                .push_bind(&event.referrer)
+                .push_bind(&event.user_agent)
+                .push_bind(&event.accept_language)
+                .push_bind(&event.ip_address)
+                .push_bind(&event.browser)
+                .push_bind(&event.os)
+                .push_bind(&event.device_class)
+                .push_bind(&event.language);
+        });
+        query.build().execute(&mut *conn).await?;
+    }
+
+    Ok(())
+}
+
+/// Get click analytics for a URL
+#[get("/{url_id}/analytics")]
+pub async fn get_url_analytics(
+    req: HttpRequest,
+    url_id: Path<String>,
+    query: Query<AnalyticsQuery>,
+    db_pool: Data<DatabasePool>,
+) -> impl Responder {
+    let url_id = url_id.into_inner();
+    let query = query.into_inner();
+    let to = query.to.unwrap_or_else(Utc::now);
+    let from = query.from.unwrap_or(to - Duration::days(7));
+    if from >= to {
+        return HttpResponse::BadRequest().json("`from` must be before `to`");
+    }
+
+    // Only the owner (or an admin) may see a URL's analytics
+    match sqlx::query_as::<_, ShortUrl>("SELECT * FROM short_urls WHERE id = ?")
+        .bind(&url_id)
+        .fetch_one(db_pool.as_ref())
+        .await
+    {
+        Ok(url) => {
+            let resource = Resource::Url {
+                owner: url.user_id.as_deref(),
+            };
+            if let Err(response) = authorize(&req, Action::Read, resource) {
+                return response;
+            }
+        }
+        Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().body("URL not found"),
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(format!("Database error: {}", e))
+        }
+    }
+
+    let histogram = format!(
+        r#"
+        SELECT {} AS value, COUNT(*) AS clicks
+        FROM click_events
+        WHERE short_url_id = ? AND clicked_at >= ? AND clicked_at < ?
+        GROUP BY value
+        ORDER BY value
+        "#,
+        query.bucket.sql_expression()
+    );
+
+    let analytics = async {
+        let clicks = sqlx::query_as::<_, ClickCount>(&histogram)
+            .bind(&url_id)
+            .bind(from)
+            .bind(to)
+            .fetch_all(db_pool.as_ref())
+            .await?;
+
+        Ok::<_, sqlx::Error>(UrlAnalytics {
+            from,
+            to,
+            total_clicks: clicks.iter().map(|bucket| bucket.clicks).sum(),
+            clicks,
+            top_referrers: top_values(&db_pool, &url_id, "referrer", "(direct)", from, to).await?,
+            top_browsers: top_values(&db_pool, &url_id, "browser", "unknown", from, to).await?,
+            top_oses: top_values(&db_pool, &url_id, "os", "unknown", from, to).await?,
+            top_languages: top_values(&db_pool, &url_id, "language", "unknown", from, to).await?,
+        })
+    };
+
+    match analytics.await {
+        Ok(analytics) => HttpResponse::Ok().json(analytics),
+        Err(e) => HttpResponse::InternalServerError().json(format!("Database error: {}", e)),
+    }
+}
+
+/// Most frequent values of a `click_events` column; `column` must be a trusted name.
+async fn top_values(
+    db_pool: &DatabasePool,
+    url_id: &str,
+    column: &str,
+    missing_label: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<ClickCount>, sqlx::Error> {
+    let query = format!(
+        r#"
+        SELECT COALESCE({column}, ?) AS value, COUNT(*) AS clicks
+        FROM click_events
+        WHERE short_url_id = ? AND clicked_at >= ? AND clicked_at < ?
+        GROUP BY value
+        ORDER BY clicks DESC
+        LIMIT ?
+        "#
+    );
+
+    sqlx::query_as::<_, ClickCount>(&query)
+        .bind(missing_label)
+        .bind(url_id)
+        .bind(from)
+        .bind(to)
+        .bind(TOP_LIMIT)
+        .fetch_all(db_pool)
+        .await
+}
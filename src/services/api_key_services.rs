@@ -0,0 +1,288 @@
+use actix_url_shortener::{
+    generate_secure_token, generate_uuid, hash_token, validation::ValidationErrors,
+};
+use actix_web::{
+    delete, get, post, put,
+    web::{Data, Json, Path},
+    HttpRequest, HttpResponse, Responder,
+};
+use chrono::{Duration, Utc};
+use serde_json::json;
+use sqlx::{types::Json as SqlJson, Executor, MySql};
+
+use crate::{
+    database::DatabasePool,
+    policy::{authorize, Action, Permission, Resource},
+    schema::{
+        api_key::{
+            ApiKey, ApiKeyCredentials, CreateApiKeyRequest, RenameApiKeyRequest,
+            API_KEY_DISPLAY_LENGTH, API_KEY_PREFIX,
+        },
+        auth::Claims,
+    },
+};
+
+/// Maximum length of an API key's name.
+const MAX_NAME_LENGTH: usize = 100;
+
+/// `last_used_at` is only written when older than this, to avoid a write per request.
+const LAST_USED_RESOLUTION_SECS: i64 = 60;
+
+/// Looks up an API key and builds the claims for the request it authenticates.
+///
+/// Returns `None` for unknown, revoked and expired keys, and keys of inactive users.
+pub async fn authenticate_api_key(
+    db_pool: &DatabasePool,
+    key: &str,
+) -> Result<Option<Claims>, sqlx::Error> {
+    let Some(credentials) = sqlx::query_as::<_, ApiKeyCredentials>(
+        r#"
+        SELECT k.id, k.user_id, k.scopes, k.expires_at, k.last_used_at, u.roles
+        FROM api_keys k
+        JOIN users u ON u.id = k.user_id
+        WHERE k.key_hash = ? AND k.revoked_at IS NULL AND u.is_active
+        "#,
+    )
+    .bind(hash_token(key))
+    .fetch_optional(db_pool)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let now = Utc::now();
+    if credentials.expires_at.is_some_and(|at| at <= now) {
+        return Ok(None);
+    }
+
+    if credentials
+        .last_used_at
+        .is_none_or(|at| now - at >= Duration::seconds(LAST_USED_RESOLUTION_SECS))
+    {
+        sqlx::query("UPDATE api_keys SET last_used_at = ? WHERE id = ?")
+            .bind(now)
+            .bind(&credentials.id)
+            .execute(db_pool)
+            .await?;
+    }
+
+    Ok(Some(Claims::for_api_key(
+        credentials.user_id,
+        credentials.roles.0,
+        credentials.id,
+        credentials.expires_at,
+        credentials.scopes.map(|scopes| scopes.0),
+    )))
+}
+
+/// Revokes every API key of a user, e.g. after their password changed.
+pub async fn revoke_user_api_keys<'c, E>(executor: E, user_id: &str) -> Result<(), sqlx::Error>
+where
+    E: Executor<'c, Database = MySql>,
+{
+    sqlx::query("UPDATE api_keys SET revoked_at = ? WHERE user_id = ? AND revoked_at IS NULL")
+        .bind(Utc::now())
+        .bind(user_id)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+/// Create an API key for the caller. The key is only ever returned in this response.
+#[post("/")]
+pub async fn create_api_key(
+    req: HttpRequest,
+    req_body: Json<CreateApiKeyRequest>,
+    db_pool: Data<DatabasePool>,
+) -> impl Responder {
+    let claims = match authorize(&req, Action::Create, Resource::ApiKey) {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+    let req_body = req_body.into_inner();
+
+    let mut errors = ValidationErrors::default();
+    let name = req_body.name.trim().to_string();
+    validate_name(&name, &mut errors);
+
+    let mut scopes = req_body.scopes;
+    if let Some(requested) = &mut scopes {
+        requested.sort();
+        requested.dedup();
+        for scope in requested.iter() {
+            if let Err(message) = scope.parse::<Permission>() {
+                errors.add("scopes", message);
+            }
+        }
+    }
+    // A scoped key must not be able to mint a key with more power than itself
+    if let Some(caller_scopes) = &claims.scopes {
+        match &scopes {
+            Some(requested) if requested.iter().all(|scope| caller_scopes.contains(scope)) => {}
+            _ => errors.add(
+                "scopes",
+                "Cannot exceed the scopes of the key making the request",
+            ),
+        }
+    }
+
+    if req_body.expires_at.is_some_and(|at| at <= Utc::now()) {
+        errors.add("expiresAt", "Must be in the future");
+    }
+    if !errors.is_empty() {
+        return HttpResponse::UnprocessableEntity().json(errors);
+    }
+
+    let key = format!("{}{}", API_KEY_PREFIX, generate_secure_token(24));
+    let api_key = ApiKey {
+        id: generate_uuid(),
+        name,
+        key_prefix: key[..API_KEY_DISPLAY_LENGTH].to_string(),
+        scopes: scopes.map(SqlJson),
+        created_at: Utc::now(),
+        expires_at: req_body.expires_at,
+        last_used_at: None,
+        revoked_at: None,
+    };
+
+    match sqlx::query(
+        r#"
+        INSERT INTO api_keys (id, user_id, name, key_prefix, key_hash, scopes, created_at, expires_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(&api_key.id)
+    .bind(&claims.sub)
+    .bind(&api_key.name)
+    .bind(&api_key.key_prefix)
+    .bind(hash_token(&key))
+    .bind(&api_key.scopes)
+    .bind(api_key.created_at)
+    .bind(api_key.expires_at)
+    .execute(db_pool.get_ref())
+    .await
+    {
+        Ok(_) => HttpResponse::Created().json(json!({
+            "message": "Store this key now, it will not be shown again",
+            "key": key,
+            "data": api_key
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(format!("Database error: {}", e)),
+    }
+}
+
+/// List the caller's API keys, including revoked ones.
+#[get("/")]
+pub async fn list_api_keys(req: HttpRequest, db_pool: Data<DatabasePool>) -> impl Responder {
+    let claims = match authorize(&req, Action::Read, Resource::ApiKey) {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    match sqlx::query_as::<_, ApiKey>(
+        r#"
+        SELECT id, name, key_prefix, scopes, created_at, expires_at, last_used_at, revoked_at
+        FROM api_keys
+        WHERE user_id = ?
+        ORDER BY created_at DESC
+        "#,
+    )
+    .bind(&claims.sub)
+    .fetch_all(db_pool.get_ref())
+    .await
+    {
+        Ok(keys) => HttpResponse::Ok().json(keys),
+        Err(e) => HttpResponse::InternalServerError().json(format!("Database error: {}", e)),
+    }
+}
+
+/// Rename one of the caller's API keys.
+#[put("/{key_id}")]
+pub async fn rename_api_key(
+    req: HttpRequest,
+    key_id: Path<String>,
+    req_body: Json<RenameApiKeyRequest>,
+    db_pool: Data<DatabasePool>,
+) -> impl Responder {
+    let claims = match authorize(&req, Action::Update, Resource::ApiKey) {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    let mut errors = ValidationErrors::default();
+    let name = req_body.into_inner().name.trim().to_string();
+    validate_name(&name, &mut errors);
+    if !errors.is_empty() {
+        return HttpResponse::UnprocessableEntity().json(errors);
+    }
+
+    let key_id = key_id.into_inner();
+    let renamed = async {
+        let result = sqlx::query("UPDATE api_keys SET name = ? WHERE id = ? AND user_id = ?")
+            .bind(&name)
+            .bind(&key_id)
+            .bind(&claims.sub)
+            .execute(db_pool.get_ref())
+            .await?;
+        if result.rows_affected() > 0 {
+            return Ok(true);
+        }
+
+        // MySQL reports 0 affected rows when the name is unchanged, so check for existence
+        sqlx::query_scalar::<_, i64>(
+            "SELECT EXISTS(SELECT 1 FROM api_keys WHERE id = ? AND user_id = ?)",
+        )
+        .bind(&key_id)
+        .bind(&claims.sub)
+        .fetch_one(db_pool.get_ref())
+        .await
+        .map(|exists| exists != 0)
+    };
+
+    match renamed.await {
+        Ok(true) => HttpResponse::Ok().json("API key renamed successfully"),
+        Ok(false) => HttpResponse::NotFound().json("API key not found"),
+        Err(e) => HttpResponse::InternalServerError().json(format!("Database error: {}", e)),
+    }
+}
+
+/// Revoke one of the caller's API keys. Revoked keys stop working immediately.
+#[delete("/{key_id}")]
+pub async fn revoke_api_key(
+    req: HttpRequest,
+    key_id: Path<String>,
+    db_pool: Data<DatabasePool>,
+) -> impl Responder {
+    let claims = match authorize(&req, Action::Delete, Resource::ApiKey) {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    match sqlx::query(
+        "UPDATE api_keys SET revoked_at = ? WHERE id = ? AND user_id = ? AND revoked_at IS NULL",
+    )
+    .bind(Utc::now())
+    .bind(key_id.into_inner())
+    .bind(&claims.sub)
+    .execute(db_pool.get_ref())
+    .await
+    {
+        Ok(result) if result.rows_affected() > 0 => {
+            HttpResponse::Ok().json("API key revoked successfully")
+        }
+        Ok(_) => HttpResponse::NotFound().json("API key not found"),
+        Err(e) => HttpResponse::InternalServerError().json(format!("Database error: {}", e)),
+    }
+}
+
+fn validate_name(name: &str, errors: &mut ValidationErrors) {
+    if name.is_empty() {
+        errors.add("name", "Must not be empty");
+    } else if name.chars().count() > MAX_NAME_LENGTH {
+        errors.add(
+            "name",
+            format!("Must be at most {} characters", MAX_NAME_LENGTH),
+        );
+    }
+}
@@ -1,110 +1,704 @@
-use std::env;
+use crate::{
+    config::AppConfig,
+    database::DatabasePool,
+    keys::KeyStore,
+    mail::{queue_email, Email},
+    mfa,
+    middleware::{check_session_csrf, find_credential, Credential},
+    schema::{
+        auth::{
+            ForgotPasswordRequest, LoginRequest, RefreshToken, ResendVerificationRequest,
+            ResetPasswordRequest, TokenPurpose, VerifyEmailRequest,
+        },
+        mfa::MfaPendingClaims,
+        user::{CreateUserRequest, User},
+    },
+    services::{
+        api_key_services::revoke_user_api_keys,
+        token_services::{
+            clear_session_cookies, consume_user_token, decode_access_token, encode_access_token,
+            invalidate_access_tokens, issue_refresh_token, issue_user_token, revoke_access_token,
+            revoke_refresh_token, revoke_user_refresh_tokens, set_session_cookies, start_session,
+            Session, REFRESH_TOKEN_COOKIE,
+        },
+    },
+    throttle::{begin_attempt, normalize_email, AttemptStart},
+};
 
-use crate::schema::{
-    auth::{Claims, LoginRequest},
-    user::{CreateUserRequest, User},
+use actix_url_shortener::{
+    hash_token,
+    password::{DummyPasswordHash, PasswordHasher},
+    validation::ValidationErrors,
 };
-use actix_web::{cookie::Cookie, get, post, web, HttpRequest, HttpResponse, Responder};
-use bcrypt::verify;
-use jsonwebtoken::{encode, EncodingKey, Header};
+use actix_web::{http::header::RETRY_AFTER, post, web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
 use serde_json::json;
+use sqlx::{MySql, Transaction};
+/// Register a new account. It stays inactive until the emailed verification link is used.
 #[post("/sign-up")]
 pub async fn register_user(
     db_pool: web::Data<sqlx::MySqlPool>,
+    config: web::Data<AppConfig>,
+    hasher: web::Data<dyn PasswordHasher>,
     req_body: web::Json<CreateUserRequest>,
 ) -> impl Responder {
+    if !config.password_login_enabled {
+        return password_login_disabled();
+    }
     // Store request in a variable to ensure its lifetime
     let req = req_body.into_inner();
+    let errors = req.validate(&config.password_policy);
+    if !errors.is_empty() {
+        return HttpResponse::UnprocessableEntity().json(errors);
+    }
 
     // Store user in a variable to extend its lifetime
     let mut user = User::default();
-    user.set_password(&req.password).unwrap();
+    if let Err(e) = user.set_password(hasher.get_ref(), &req.password) {
+        eprintln!("Failed to hash password: {}", e);
+        return HttpResponse::InternalServerError().json("Failed to register user");
+    }
     user.email = req.email; // Use `clone` to ensure a valid reference
     user.username = req.username; // Use `clone` to ensure a valid reference
-    user.is_active = true;
+    user.is_active = false; // Activated by `verify_email`
     let query = r#"
         INSERT INTO users (id, username, email, password, is_active, roles)
         VALUES (?, ?, ?, ?, ?, ?)
     "#;
 
-    let result = sqlx::query(query)
-        .bind(&user.id)
-        .bind(&user.username)
-        .bind(&user.email)
-        .bind(&user.password)
-        .bind(&user.is_active)
-        .bind(&user.roles)
-        .execute(db_pool.get_ref())
-        .await;
-
-    match result {
-        Ok(_) => {
-            let secret_key = env::var("SECRET").expect("msg");
-            let claims = Claims::new(user.id.clone(), user.roles.first().unwrap().to_string());
-            let token = encode(
-                &Header::default(),
-                &claims,
-                &EncodingKey::from_secret(secret_key.as_bytes()),
-            )
-            .unwrap();
-
-            HttpResponse::Created()
-                .cookie(
-                    Cookie::build("access_token", token)
-                        .path("/")
-                        .http_only(true)
-                        .finish(),
-                )
-                .json(json!({
-                    "message": "User registered successfully",
-                    "data":user
-                }))
-        }
-        Err(_) => HttpResponse::Conflict().json(json!({
-            "error": "Username or email already exists"
+    let registered = async {
+        let mut tx = db_pool.begin().await?;
+        sqlx::query(query)
+            .bind(&user.id)
+            .bind(&user.username)
+            .bind(&user.email)
+            .bind(&user.password)
+            .bind(user.is_active)
+            .bind(&user.roles)
+            .execute(&mut *tx)
+            .await?;
+        queue_verification_email(&mut tx, &config, &user.id, &user.email).await?;
+        tx.commit().await
+    };
+
+    match registered.await {
+        Ok(_) => HttpResponse::Created().json(json!({
+            "message": "User registered successfully, check your email to activate the account",
+            "data":user
         })),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            HttpResponse::Conflict().json(json!({
+                "error": "Username or email already exists"
+            }))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(format!("Database error: {}", e)),
+    }
+}
+
+/// Activate an account with the token from its verification email.
+#[post("/verify-email")]
+pub async fn verify_email(
+    db_pool: web::Data<DatabasePool>,
+    req_body: web::Json<VerifyEmailRequest>,
+) -> impl Responder {
+    let token = req_body.into_inner().token;
+    let verified = async {
+        let mut tx = db_pool.begin().await?;
+        let Some(user_id) =
+            consume_user_token(&mut tx, &token, TokenPurpose::EmailVerification).await?
+        else {
+            return Ok(false);
+        };
+
+        sqlx::query("UPDATE users SET is_active = TRUE WHERE id = ?")
+            .bind(&user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(true)
+    };
+
+    match verified.await {
+        Ok(true) => HttpResponse::Ok().json("Email verified, the account is now active"),
+        Ok(false) => HttpResponse::BadRequest().json("Invalid or expired verification token"),
+        Err(e) => HttpResponse::InternalServerError().json(format!("Database error: {}", e)),
+    }
+}
+
+/// Send a new verification link to an inactive account.
+///
+/// At most one email per `VERIFICATION_RESEND_INTERVAL_SECS` is sent. Always answers
+/// `202 Accepted`, so the response does not reveal whether the account exists.
+#[post("/resend-verification")]
+pub async fn resend_verification(
+    db_pool: web::Data<DatabasePool>,
+    config: web::Data<AppConfig>,
+    req_body: web::Json<ResendVerificationRequest>,
+) -> impl Responder {
+    let email = req_body.into_inner().email;
+    let user = match sqlx::query_as::<_, (String, bool, Option<DateTime<Utc>>)>(
+        r#"
+        SELECT u.id, u.is_active, MAX(t.created_at)
+        FROM users u
+        LEFT JOIN user_tokens t ON t.user_id = u.id AND t.purpose = ?
+        WHERE u.email = ?
+        GROUP BY u.id, u.is_active
+        "#,
+    )
+    .bind(TokenPurpose::EmailVerification.as_str())
+    .bind(&email)
+    .fetch_optional(db_pool.get_ref())
+    .await
+    {
+        Ok(user) => user,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(format!("Database error: {}", e))
+        }
+    };
+
+    let throttled = |last_sent: Option<DateTime<Utc>>| {
+        last_sent.is_some_and(|at| Utc::now() - at < config.verification_resend_interval)
+    };
+    if let Some((user_id, false, last_sent)) = user {
+        if !throttled(last_sent) {
+            let queued = async {
+                let mut tx = db_pool.begin().await?;
+                queue_verification_email(&mut tx, &config, &user_id, &email).await?;
+                tx.commit().await
+            };
+            if let Err(e) = queued.await {
+                return HttpResponse::InternalServerError().json(format!("Database error: {}", e));
+            }
+        }
     }
+
+    HttpResponse::Accepted()
+        .json("If an inactive account exists for this email, a verification link has been sent")
 }
 
+/// Issues an email verification token and queues the email carrying it.
+async fn queue_verification_email(
+    tx: &mut Transaction<'_, MySql>,
+    config: &AppConfig,
+    user_id: &str,
+    email: &str,
+) -> Result<(), sqlx::Error> {
+    let token = issue_user_token(
+        tx,
+        user_id,
+        TokenPurpose::EmailVerification,
+        config.email_verification_ttl,
+    )
+    .await?;
+    let email = Email {
+        to: email.to_string(),
+        subject: "Verify your email address".to_string(),
+        body: format!(
+            "Welcome! Follow this link to activate your account:\n{}\n\n\
+             The link is valid for {} hours. If you did not sign up, you can ignore this email.",
+            with_token(&config.email_verification_url, &token),
+            config.email_verification_ttl.num_hours()
+        ),
+    };
+    queue_email(&mut **tx, &email).await
+}
+
+/// Sign in with email and password.
+///
+/// Failed attempts are counted per email and per client IP: after a few of them each
+/// attempt is slowed down, and too many lock the email or IP out for a while. Unknown
+/// emails go through the same steps, including a password hash check, so responses do
+/// not reveal whether an account exists. A hash made with an outdated algorithm or
+/// parameters is replaced once the password is confirmed. When two-factor
+/// authentication is enabled, the response carries an `mfaToken` to finish signing in
+/// with `/auth/mfa/verify` instead.
 #[post("/sign-in")]
 pub async fn login_user(
+    http_req: HttpRequest,
     db_pool: web::Data<sqlx::MySqlPool>,
+    keys: web::Data<KeyStore>,
+    config: web::Data<AppConfig>,
+    hasher: web::Data<dyn PasswordHasher>,
+    dummy_hash: web::Data<DummyPasswordHash>,
     req_body: web::Json<LoginRequest>,
 ) -> impl Responder {
+    if !config.password_login_enabled {
+        return password_login_disabled();
+    }
     let req = req_body.into_inner();
-    let user: User = match sqlx::query_as::<_, User>("SELECT * FROM users WHERE email = ?")
-        .bind(req.email)
-        .fetch_one(db_pool.get_ref())
+    let throttle = &config.login_throttle;
+    let email = normalize_email(&req.email);
+    // Forwarded headers are set by the client, so they only count from trusted proxies
+    let ip = config
+        .trusted_proxies
+        .client_ip_of(&http_req)
+        .map(|ip| ip.to_string());
+
+    let attempt = match begin_attempt(&db_pool, throttle, &email, ip.as_deref()).await {
+        Ok(AttemptStart::Allowed(attempt)) => attempt,
+        Ok(AttemptStart::Locked(locked_until)) => return too_many_attempts(locked_until),
+        Err(e) => {
+            eprintln!("Failed to check sign-in attempts: {:?}", e);
+            return HttpResponse::InternalServerError().json("Failed to sign in");
+        }
+    };
+    tokio::time::sleep(throttle.delay(attempt.prior_failures)).await;
+
+    let user = match sqlx::query_as::<_, User>("SELECT * FROM users WHERE email = ?")
+        .bind(&req.email)
+        .fetch_optional(db_pool.get_ref())
         .await
     {
         Ok(user) => user,
-        Err(_) => {
-            return HttpResponse::Unauthorized()
-                .json(json!({ "error": "Invalid email or password" }))
+        Err(e) => {
+            eprintln!("Failed to fetch user: {:?}", e);
+            return HttpResponse::InternalServerError().json("Failed to sign in");
         }
     };
+    // Hash against a throwaway password when the email is unknown, to take as long as a real check
+    let password_hash = match &user {
+        Some(user) => user.password.as_str(),
+        None => dummy_hash.as_str(),
+    };
+    let password_matches = hasher.verify(&req.password, password_hash).unwrap_or(false);
+    // Hashes in another format or with other parameters, like the unusable one of single
+    // sign-on users, can be checked much faster; pad them with a check at the current cost
+    if user.is_some() && hasher.needs_rehash(password_hash) {
+        let _ = hasher.verify(&req.password, dummy_hash.as_str());
+    }
 
-    if verify(req.password, &user.password).unwrap_or(false) {
-        let claims = Claims::new(user.id, user.roles.first().unwrap().to_string());
+    let Some(user) = user.filter(|_| password_matches) else {
+        if let Err(e) = attempt.fail(&db_pool, throttle).await {
+            eprintln!("Failed to record sign-in failure: {:?}", e);
+        }
+        return HttpResponse::Unauthorized().json(json!({ "error": "Invalid email or password" }));
+    };
+    rehash_if_outdated(&db_pool, hasher.get_ref(), &user, &req.password).await;
 
-        let secret_key = env::var("SECRET").expect("msg");
+    let mfa_enabled = match mfa::is_enabled(&db_pool, &user.id).await {
+        Ok(mfa_enabled) => mfa_enabled,
+        Err(e) => {
+            eprintln!("Failed to check two-factor authentication: {:?}", e);
+            return HttpResponse::InternalServerError().json("Failed to sign in");
+        }
+    };
 
-        let token = encode(
-            &Header::default(),
-            &claims,
-            &EncodingKey::from_secret(secret_key.as_bytes()),
+    // The password was right, so the attempt is no failure. Earlier failures are only
+    // forgotten once signing in is complete
+    let settled = if user.is_active && !mfa_enabled {
+        attempt.succeed(&db_pool).await
+    } else {
+        attempt.release(&db_pool).await
+    };
+    if let Err(e) = settled {
+        eprintln!("Failed to settle sign-in attempt: {:?}", e);
+    }
+
+    if !user.is_active {
+        return HttpResponse::Forbidden()
+            .json(json!({ "error": "Verify your email address before signing in" }));
+    }
+
+    // With two-factor authentication the password only earns a token for `/auth/mfa/verify`
+    if mfa_enabled {
+        let claims = MfaPendingClaims::new(user.id, config.mfa_token_ttl);
+        return match keys.sign(&claims) {
+            Ok(mfa_token) => {
+                HttpResponse::Ok().json(json!({ "mfaRequired": true, "mfaToken": mfa_token }))
+            }
+            Err(e) => {
+                eprintln!("Failed to sign MFA token: {:?}", e);
+                HttpResponse::InternalServerError().json("Failed to sign in")
+            }
+        };
+    }
+
+    signed_in(&db_pool, &keys, &config, &user).await
+}
+
+/// Starts a session for a fully authenticated user and returns its tokens.
+pub async fn signed_in(
+    db_pool: &DatabasePool,
+    keys: &KeyStore,
+    config: &AppConfig,
+    user: &User,
+) -> HttpResponse {
+    match start_session(db_pool, keys, config, &user.id, &user.roles).await {
+        Ok(session) => {
+            let mut response = HttpResponse::Ok();
+            set_session_cookies(&mut response, config, &session);
+            response.json(json!({ "token": session.access_token }))
+        }
+        Err(e) => {
+            eprintln!("Failed to start session: {}", e);
+            HttpResponse::InternalServerError().json("Failed to start session")
+        }
+    }
+}
+
+fn password_login_disabled() -> HttpResponse {
+    HttpResponse::Forbidden()
+        .json(json!({ "error": "Password sign-in is disabled, use single sign-on" }))
+}
+
+/// `429` for a locked-out email or IP, telling the client when to try again.
+pub fn too_many_attempts(locked_until: DateTime<Utc>) -> HttpResponse {
+    let retry_after = (locked_until - Utc::now()).num_seconds().max(1);
+    HttpResponse::TooManyRequests()
+        .insert_header((RETRY_AFTER, retry_after.to_string()))
+        .json(json!({ "error": "Too many failed sign-in attempts, try again later" }))
+}
+
+/// Replaces a hash made with an outdated algorithm or parameters, now that the
+/// password is known. Failing to is not worth failing the sign-in over.
+async fn rehash_if_outdated(
+    db_pool: &DatabasePool,
+    hasher: &dyn PasswordHasher,
+    user: &User,
+    password: &str,
+) {
+    if !hasher.needs_rehash(&user.password) {
+        return;
+    }
+    let rehashed = match hasher.hash(password) {
+        Ok(hash) => hash,
+        Err(e) => {
+            eprintln!("Failed to rehash password: {}", e);
+            return;
+        }
+    };
+    // Leave the hash alone if the password was changed in the meantime
+    if let Err(e) = sqlx::query("UPDATE users SET password = ? WHERE id = ? AND password = ?")
+        .bind(rehashed)
+        .bind(&user.id)
+        .bind(&user.password)
+        .execute(db_pool)
+        .await
+    {
+        eprintln!("Failed to store rehashed password: {:?}", e);
+    }
+}
+
+/// Exchange the refresh token cookie for a new access token, rotating the refresh token.
+///
+/// Presenting a refresh token that was already rotated means it leaked, so every
+/// session of its user is revoked. Being cookie-based, it must pass the CSRF checks.
+#[post("/refresh")]
+pub async fn refresh_session(
+    req: HttpRequest,
+    db_pool: web::Data<DatabasePool>,
+    keys: web::Data<KeyStore>,
+    config: web::Data<AppConfig>,
+) -> impl Responder {
+    let Some(cookie) = req.cookie(REFRESH_TOKEN_COOKIE) else {
+        return HttpResponse::Unauthorized().json("Missing refresh token");
+    };
+    if let Err(err) = check_session_csrf(&req, &config) {
+        return err.error_response();
+    }
+
+    let stored = match sqlx::query_as::<_, RefreshToken>(
+        r#"
+        SELECT id, user_id, family_id, expires_at, revoked_at
+        FROM refresh_tokens
+        WHERE token_hash = ?
+        "#,
+    )
+    .bind(hash_token(cookie.value()))
+    .fetch_one(db_pool.get_ref())
+    .await
+    {
+        Ok(stored) => stored,
+        Err(sqlx::Error::RowNotFound) => return end_session(&config, "Invalid refresh token"),
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(format!("Database error: {}", e))
+        }
+    };
+
+    if stored.revoked_at.is_some() {
+        return revoke_all_sessions(&db_pool, &config, &stored.user_id).await;
+    }
+    if stored.expires_at <= Utc::now() {
+        return end_session(&config, "Refresh token expired");
+    }
+
+    // Roles may have changed since the last token was issued
+    let user = match sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = ?")
+        .bind(&stored.user_id)
+        .fetch_one(db_pool.get_ref())
+        .await
+    {
+        Ok(user) if user.is_active => user,
+        Ok(_) => return end_session(&config, "Account is not active"),
+        Err(sqlx::Error::RowNotFound) => return end_session(&config, "Invalid refresh token"),
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(format!("Database error: {}", e))
+        }
+    };
+
+    let rotated = async {
+        let mut tx = db_pool.begin().await?;
+        let (refresh_token, new_id) =
+            issue_refresh_token(&mut *tx, &config, &user.id, &stored.family_id).await?;
+
+        // Losing this race to a concurrent refresh also counts as reuse
+        let revoked = sqlx::query(
+            r#"
+            UPDATE refresh_tokens
+            SET revoked_at = ?, replaced_by = ?
+            WHERE id = ? AND revoked_at IS NULL
+            "#,
         )
-        .unwrap();
-
-        HttpResponse::Ok()
-            .cookie(
-                Cookie::build("access_token", &token)
-                    .path("/")
-                    .http_only(true)
-                    .finish(),
+        .bind(Utc::now())
+        .bind(&new_id)
+        .bind(&stored.id)
+        .execute(&mut *tx)
+        .await?;
+        if revoked.rows_affected() != 1 {
+            return Ok(None);
+        }
+
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Some(refresh_token))
+    };
+
+    let refresh_token = match rotated.await {
+        Ok(Some(refresh_token)) => refresh_token,
+        Ok(None) => return revoke_all_sessions(&db_pool, &config, &stored.user_id).await,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(format!("Database error: {}", e))
+        }
+    };
+
+    match encode_access_token(&keys, &config, &user.id, &user.roles) {
+        Ok(access_token) => {
+            let session = Session {
+                access_token,
+                refresh_token,
+            };
+            let mut response = HttpResponse::Ok();
+            set_session_cookies(&mut response, &config, &session);
+            response.json(json!({ "token": session.access_token }))
+        }
+        Err(e) => {
+            eprintln!("Failed to sign access token: {}", e);
+            HttpResponse::InternalServerError().json("Failed to refresh session")
+        }
+    }
+}
+
+/// Sign out: clear the session cookies and revoke both tokens server-side.
+///
+/// Succeeds even without a valid session, so a client can always clear its cookies.
+/// Requests carrying session cookies must pass the CSRF checks.
+#[post("/sign-out")]
+pub async fn logout_user(
+    req: HttpRequest,
+    db_pool: web::Data<DatabasePool>,
+    keys: web::Data<KeyStore>,
+    config: web::Data<AppConfig>,
+) -> impl Responder {
+    if let Err(err) = check_session_csrf(&req, &config) {
+        return err.error_response();
+    }
+
+    // The token the middleware would authenticate with, from the cookie or the Bearer
+    // header. An expired or forged one needs no revocation, and API keys are revoked
+    // through `/api-keys`
+    let access_token = match find_credential(&req, &config) {
+        Some(Credential::Token(token, _)) => Some(token),
+        _ => None,
+    };
+    if let Some(claims) = access_token.and_then(|token| decode_access_token(&keys, &token).ok()) {
+        if let Err(e) = revoke_access_token(&db_pool, &claims).await {
+            eprintln!("Failed to revoke access token: {:?}", e);
+            return HttpResponse::InternalServerError().json("Failed to sign out");
+        }
+    }
+
+    if let Some(cookie) = req.cookie(REFRESH_TOKEN_COOKIE) {
+        if let Err(e) = revoke_refresh_token(&db_pool, cookie.value()).await {
+            eprintln!("Failed to revoke refresh token: {:?}", e);
+            return HttpResponse::InternalServerError().json("Failed to sign out");
+        }
+    }
+
+    let mut response = HttpResponse::Ok();
+    clear_session_cookies(&mut response, &config);
+    response.json("Signed out successfully")
+}
+
+/// Request a password reset link by email.
+///
+/// Always answers `202 Accepted`, so the response does not reveal whether an
+/// account exists for the address. At most one link per `password_reset_resend_interval`
+/// is mailed to an address, so the endpoint cannot be used to flood an inbox.
+#[post("/forgot-password")]
+pub async fn forgot_password(
+    db_pool: web::Data<DatabasePool>,
+    config: web::Data<AppConfig>,
+    req_body: web::Json<ForgotPasswordRequest>,
+) -> impl Responder {
+    let email = req_body.into_inner().email;
+    let user = match sqlx::query_as::<_, (String, Option<DateTime<Utc>>)>(
+        r#"
+        SELECT u.id, MAX(t.created_at)
+        FROM users u
+        LEFT JOIN user_tokens t ON t.user_id = u.id AND t.purpose = ?
+        WHERE u.email = ?
+        GROUP BY u.id
+        "#,
+    )
+    .bind(TokenPurpose::PasswordReset.as_str())
+    .bind(&email)
+    .fetch_optional(db_pool.get_ref())
+    .await
+    {
+        Ok(user) => user,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(format!("Database error: {}", e))
+        }
+    };
+
+    let throttled = |last_sent: Option<DateTime<Utc>>| {
+        last_sent.is_some_and(|at| Utc::now() - at < config.password_reset_resend_interval)
+    };
+    if let Some((user_id, _)) = user.filter(|(_, last_sent)| !throttled(*last_sent)) {
+        let queued = async {
+            let mut tx = db_pool.begin().await?;
+            let token = issue_user_token(
+                &mut tx,
+                &user_id,
+                TokenPurpose::PasswordReset,
+                config.password_reset_ttl,
             )
-            .json(json!({ "token": token }))
-    } else {
-        HttpResponse::Unauthorized().json(json!({ "error": "Invalid email or password" }))
+            .await?;
+            let link = with_token(&config.password_reset_url, &token);
+            let email = Email {
+                to: email,
+                subject: "Reset your password".to_string(),
+                body: format!(
+                    "Someone asked to reset the password of your account.\n\n\
+                     Follow this link within {} minutes to choose a new one:\n{}\n\n\
+                     If this was not you, you can ignore this email.",
+                    config.password_reset_ttl.num_minutes(),
+                    link
+                ),
+            };
+            queue_email(&mut *tx, &email).await?;
+            tx.commit().await
+        };
+        if let Err(e) = queued.await {
+            return HttpResponse::InternalServerError().json(format!("Database error: {}", e));
+        }
+    }
+
+    HttpResponse::Accepted().json("If an account exists for this email, a reset link has been sent")
+}
+
+/// Choose a new password with a token from a reset email.
+///
+/// The token works once; every existing session of the user is ended.
+#[post("/reset-password")]
+pub async fn reset_password(
+    db_pool: web::Data<DatabasePool>,
+    config: web::Data<AppConfig>,
+    hasher: web::Data<dyn PasswordHasher>,
+    req_body: web::Json<ResetPasswordRequest>,
+) -> impl Responder {
+    let req = req_body.into_inner();
+    // Checked without the username first, so an overlong password is never hashed
+    let mut errors = ValidationErrors::default();
+    config
+        .password_policy
+        .validate_field(&mut errors, "new_password", &req.new_password, "");
+    if !errors.is_empty() {
+        return HttpResponse::UnprocessableEntity().json(errors);
+    }
+    let password_hash = match hasher.hash(&req.new_password) {
+        Ok(password_hash) => password_hash,
+        Err(e) => {
+            eprintln!("Failed to hash password: {}", e);
+            return HttpResponse::InternalServerError().json("Failed to reset password");
+        }
+    };
+
+    let reset = async {
+        let mut tx = db_pool.begin().await?;
+        let Some(user_id) =
+            consume_user_token(&mut tx, &req.token, TokenPurpose::PasswordReset).await?
+        else {
+            return Ok(Err(
+                HttpResponse::BadRequest().json("Invalid or expired reset token")
+            ));
+        };
+
+        // Rejecting the password rolls back, so the token can be used again
+        let username = sqlx::query_scalar::<_, String>("SELECT username FROM users WHERE id = ?")
+            .bind(&user_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        let mut errors = ValidationErrors::default();
+        config.password_policy.validate_field(
+            &mut errors,
+            "new_password",
+            &req.new_password,
+            &username,
+        );
+        if !errors.is_empty() {
+            return Ok(Err(HttpResponse::UnprocessableEntity().json(errors)));
+        }
+
+        sqlx::query("UPDATE users SET password = ? WHERE id = ?")
+            .bind(&password_hash)
+            .bind(&user_id)
+            .execute(&mut *tx)
+            .await?;
+        invalidate_access_tokens(&mut *tx, &user_id).await?;
+        revoke_user_refresh_tokens(&mut *tx, &user_id).await?;
+        // Keys minted by whoever knew the old password must not outlive it
+        revoke_user_api_keys(&mut *tx, &user_id).await?;
+
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Ok(()))
+    };
+
+    match reset.await {
+        Ok(Ok(())) => HttpResponse::Ok().json("Password reset successfully"),
+        Ok(Err(response)) => response,
+        Err(e) => HttpResponse::InternalServerError().json(format!("Database error: {}", e)),
+    }
+}
+
+/// Appends `token` to a link as the `token` query parameter.
+fn with_token(url: &str, token: &str) -> String {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{}{}token={}", url, separator, token)
+}
+
+/// 401 that also clears the session cookies.
+fn end_session(config: &AppConfig, message: &str) -> HttpResponse {
+    let mut response = HttpResponse::Unauthorized();
+    clear_session_cookies(&mut response, config);
+    response.json(message)
+}
+
+/// Handles a reused refresh token by revoking every refresh token of its user.
+async fn revoke_all_sessions(
+    db_pool: &DatabasePool,
+    config: &AppConfig,
+    user_id: &str,
+) -> HttpResponse {
+    if let Err(e) = revoke_user_refresh_tokens(db_pool, user_id).await {
+        eprintln!("Failed to revoke refresh tokens: {:?}", e);
+    }
+    // The thief may still hold an access token from the stolen refresh token
+    if let Err(e) = invalidate_access_tokens(db_pool, user_id).await {
+        eprintln!("Failed to invalidate access tokens: {:?}", e);
     }
+    end_session(
+        config,
+        "Refresh token reuse detected, all sessions were revoked",
+    )
 }
@@ -0,0 +1,46 @@
+use actix_web::{
+    delete, get,
+    web::{Data, Path},
+    HttpRequest, HttpResponse, Responder,
+};
+
+use crate::{
+    database::DatabasePool,
+    policy::{authorize, Action, Resource},
+    throttle::{clear_lockout, list_lockouts},
+};
+
+/// Lists the sign-in lockouts currently in effect.
+#[get("/")]
+pub async fn list_active_lockouts(req: HttpRequest, db: Data<DatabasePool>) -> impl Responder {
+    if let Err(response) = authorize(&req, Action::Read, Resource::User) {
+        return response;
+    }
+    match list_lockouts(&db).await {
+        Ok(lockouts) => HttpResponse::Ok().json(lockouts),
+        Err(err) => {
+            eprintln!("Failed to list lockouts: {:?}", err);
+            HttpResponse::InternalServerError().json("Failed to retrieve lockouts")
+        }
+    }
+}
+
+/// Lifts a lockout early and forgets the failed attempts behind it.
+#[delete("/{lockout_id}")]
+pub async fn delete_lockout(
+    req: HttpRequest,
+    lockout_id: Path<u64>,
+    db: Data<DatabasePool>,
+) -> impl Responder {
+    if let Err(response) = authorize(&req, Action::Delete, Resource::User) {
+        return response;
+    }
+    match clear_lockout(&db, lockout_id.into_inner()).await {
+        Ok(true) => HttpResponse::Ok().json("Lockout cleared"),
+        Ok(false) => HttpResponse::NotFound().json("Lockout not found"),
+        Err(err) => {
+            eprintln!("Failed to clear lockout: {:?}", err);
+            HttpResponse::InternalServerError().json("Failed to clear lockout")
+        }
+    }
+}
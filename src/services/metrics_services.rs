@@ -0,0 +1,30 @@
+use actix_web::{get, web::Data, HttpResponse, Responder};
+
+use crate::{cache::RedirectCache, clicks::ClickCounter};
+
+/// Operational metrics in the Prometheus text format.
+#[get("/metrics")]
+pub async fn metrics(clicks: Data<ClickCounter>, cache: Data<RedirectCache>) -> impl Responder {
+    let body = format!(
+        "# HELP click_counter_pending_clicks Clicks received but not yet flushed to the database.\n\
+         # TYPE click_counter_pending_clicks gauge\n\
+         click_counter_pending_clicks {}\n\
+         # HELP redirect_cache_hits_total Redirect lookups answered from the cache.\n\
+         # TYPE redirect_cache_hits_total counter\n\
+         redirect_cache_hits_total {}\n\
+         # HELP redirect_cache_misses_total Redirect lookups that went to the database.\n\
+         # TYPE redirect_cache_misses_total counter\n\
+         redirect_cache_misses_total {}\n\
+         # HELP redirect_cache_entries Short codes currently cached.\n\
+         # TYPE redirect_cache_entries gauge\n\
+         redirect_cache_entries {}\n",
+        clicks.pending(),
+        cache.hits(),
+        cache.misses(),
+        cache.len()
+    );
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body)
+}
@@ -0,0 +1,462 @@
+use actix_url_shortener::validation::ValidationErrors;
+use actix_web::{
+    delete, post,
+    web::{Data, Json},
+    HttpMessage, HttpRequest, HttpResponse, Responder,
+};
+use chrono::{DateTime, Utc};
+use serde_json::json;
+
+use crate::{
+    config::AppConfig,
+    database::DatabasePool,
+    keys::KeyStore,
+    mfa::{
+        accept_totp, find_credential, generate_secret, provisioning_uri, qr_code_svg,
+        replace_recovery_codes, verify_second_factor,
+    },
+    middleware::AuthSource,
+    schema::{
+        auth::Claims,
+        mfa::{
+            MfaCodeRequest, MfaEnrollment, MfaPendingClaims, VerifyMfaRequest, MFA_PENDING_AUDIENCE,
+        },
+        user::User,
+    },
+    services::{
+        auth_services::{signed_in, too_many_attempts},
+        token_services::use_up_token,
+    },
+    throttle::{begin_attempt, normalize_email, Attempt, AttemptStart},
+};
+
+/// Start enrolling an authenticator app. The secret only takes effect once a code
+/// from the app is confirmed; starting again replaces a pending secret.
+#[post("/enroll")]
+pub async fn enroll_mfa(
+    req: HttpRequest,
+    db_pool: Data<DatabasePool>,
+    config: Data<AppConfig>,
+) -> impl Responder {
+    let claims = match signed_in_claims(&req) {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    match find_credential(&db_pool, &claims.sub).await {
+        Ok(Some(credential)) if credential.is_enabled() => {
+            return HttpResponse::Conflict().json("Two-factor authentication is already enabled")
+        }
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("Failed to fetch TOTP credential: {:?}", e);
+            return HttpResponse::InternalServerError().json("Failed to start enrollment");
+        }
+    }
+    let email = match sqlx::query_scalar::<_, String>("SELECT email FROM users WHERE id = ?")
+        .bind(&claims.sub)
+        .fetch_optional(db_pool.get_ref())
+        .await
+    {
+        Ok(Some(email)) => email,
+        Ok(None) => return HttpResponse::NotFound().json("User not found"),
+        Err(e) => {
+            eprintln!("Failed to fetch user: {:?}", e);
+            return HttpResponse::InternalServerError().json("Failed to start enrollment");
+        }
+    };
+
+    let secret = generate_secret();
+    let enrollment = provisioning_uri(&secret, &config.mfa_issuer, &email).and_then(|uri| {
+        Ok(MfaEnrollment {
+            qr_code_svg: qr_code_svg(&uri)?,
+            otpauth_uri: uri,
+            secret: secret.clone(),
+        })
+    });
+    let enrollment = match enrollment {
+        Ok(enrollment) => enrollment,
+        Err(e) => {
+            eprintln!("Failed to build provisioning URI: {}", e);
+            return HttpResponse::InternalServerError().json("Failed to start enrollment");
+        }
+    };
+
+    match sqlx::query(
+        r#"
+        INSERT INTO totp_credentials (user_id, secret, created_at)
+        VALUES (?, ?, ?)
+        ON DUPLICATE KEY UPDATE
+            secret = VALUES(secret),
+            created_at = VALUES(created_at),
+            last_used_step = NULL
+        "#,
+    )
+    .bind(&claims.sub)
+    .bind(&secret)
+    .bind(Utc::now())
+    .execute(db_pool.get_ref())
+    .await
+    {
+        Ok(_) => HttpResponse::Ok().json(enrollment),
+        Err(e) => {
+            eprintln!("Failed to store TOTP secret: {:?}", e);
+            HttpResponse::InternalServerError().json("Failed to start enrollment")
+        }
+    }
+}
+
+/// Confirm enrollment with a code from the app, enabling two-factor authentication.
+/// Returns the recovery codes, which are not shown again.
+#[post("/confirm")]
+pub async fn confirm_mfa(
+    req: HttpRequest,
+    db_pool: Data<DatabasePool>,
+    req_body: Json<MfaCodeRequest>,
+) -> impl Responder {
+    let claims = match signed_in_claims(&req) {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    let credential = match find_credential(&db_pool, &claims.sub).await {
+        Ok(Some(credential)) if credential.is_enabled() => {
+            return HttpResponse::Conflict().json("Two-factor authentication is already enabled")
+        }
+        Ok(Some(credential)) => credential,
+        Ok(None) => return HttpResponse::NotFound().json("No enrollment in progress"),
+        Err(e) => {
+            eprintln!("Failed to fetch TOTP credential: {:?}", e);
+            return HttpResponse::InternalServerError().json("Failed to confirm enrollment");
+        }
+    };
+
+    match accept_totp(&db_pool, &claims.sub, &credential, &req_body.code).await {
+        Ok(true) => {}
+        Ok(false) => return invalid_code(),
+        Err(e) => {
+            eprintln!("Failed to check TOTP code: {:?}", e);
+            return HttpResponse::InternalServerError().json("Failed to confirm enrollment");
+        }
+    }
+
+    let enabled = async {
+        let mut tx = db_pool.begin().await?;
+        sqlx::query("UPDATE totp_credentials SET enabled_at = ? WHERE user_id = ?")
+            .bind(Utc::now())
+            .bind(&claims.sub)
+            .execute(&mut *tx)
+            .await?;
+        let recovery_codes = replace_recovery_codes(&mut tx, &claims.sub).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(recovery_codes)
+    };
+    match enabled.await {
+        Ok(recovery_codes) => HttpResponse::Ok().json(json!({ "recoveryCodes": recovery_codes })),
+        Err(e) => {
+            eprintln!("Failed to enable two-factor authentication: {:?}", e);
+            HttpResponse::InternalServerError().json("Failed to confirm enrollment")
+        }
+    }
+}
+
+/// Replace the recovery codes, e.g. when they run out. Needs a current code.
+///
+/// Wrong codes count as failed sign-ins, like at `/auth/mfa/verify`.
+#[post("/recovery-codes")]
+pub async fn regenerate_recovery_codes(
+    req: HttpRequest,
+    db_pool: Data<DatabasePool>,
+    config: Data<AppConfig>,
+    req_body: Json<MfaCodeRequest>,
+) -> impl Responder {
+    let claims = match signed_in_claims(&req) {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    match check_code(&req, &db_pool, &config, &claims.sub, &req_body.code).await {
+        Ok(CodeCheck::Accepted(attempt)) => release(attempt, &db_pool).await,
+        Ok(CodeCheck::Rejected) => return invalid_code(),
+        Ok(CodeCheck::Locked(locked_until)) => return too_many_attempts(locked_until),
+        Err(e) => {
+            eprintln!("Failed to check second factor: {:?}", e);
+            return HttpResponse::InternalServerError().json("Failed to replace recovery codes");
+        }
+    }
+
+    let replaced = async {
+        let mut tx = db_pool.begin().await?;
+        let recovery_codes = replace_recovery_codes(&mut tx, &claims.sub).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(recovery_codes)
+    };
+    match replaced.await {
+        Ok(recovery_codes) => HttpResponse::Ok().json(json!({ "recoveryCodes": recovery_codes })),
+        Err(e) => {
+            eprintln!("Failed to replace recovery codes: {:?}", e);
+            HttpResponse::InternalServerError().json("Failed to replace recovery codes")
+        }
+    }
+}
+
+/// Turn two-factor authentication off. Needs a current code or a recovery code.
+///
+/// Wrong codes count as failed sign-ins, like at `/auth/mfa/verify`.
+#[delete("/")]
+pub async fn disable_mfa(
+    req: HttpRequest,
+    db_pool: Data<DatabasePool>,
+    config: Data<AppConfig>,
+    req_body: Json<MfaCodeRequest>,
+) -> impl Responder {
+    let claims = match signed_in_claims(&req) {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    match check_code(&req, &db_pool, &config, &claims.sub, &req_body.code).await {
+        Ok(CodeCheck::Accepted(attempt)) => release(attempt, &db_pool).await,
+        Ok(CodeCheck::Rejected) => return invalid_code(),
+        Ok(CodeCheck::Locked(locked_until)) => return too_many_attempts(locked_until),
+        Err(e) => {
+            eprintln!("Failed to check second factor: {:?}", e);
+            return HttpResponse::InternalServerError()
+                .json("Failed to disable two-factor authentication");
+        }
+    }
+
+    let disabled = async {
+        let mut tx = db_pool.begin().await?;
+        sqlx::query("DELETE FROM recovery_codes WHERE user_id = ?")
+            .bind(&claims.sub)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM totp_credentials WHERE user_id = ?")
+            .bind(&claims.sub)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await
+    };
+    match disabled.await {
+        Ok(()) => HttpResponse::Ok().json("Two-factor authentication disabled"),
+        Err(e) => {
+            eprintln!("Failed to disable two-factor authentication: {:?}", e);
+            HttpResponse::InternalServerError().json("Failed to disable two-factor authentication")
+        }
+    }
+}
+
+/// Finish a two-factor sign-in with the `mfaToken` from `/auth/sign-in` and a code
+/// from the authenticator app or a recovery code.
+///
+/// Wrong codes count as failed sign-ins, so guessing runs into the same lockouts. A
+/// wrong code may be retried with the same `mfaToken`, but it only signs in once.
+#[post("/mfa/verify")]
+pub async fn verify_mfa(
+    http_req: HttpRequest,
+    db_pool: Data<DatabasePool>,
+    keys: Data<KeyStore>,
+    config: Data<AppConfig>,
+    req_body: Json<VerifyMfaRequest>,
+) -> impl Responder {
+    let req = req_body.into_inner();
+    let pending: MfaPendingClaims =
+        match keys.verify_for_audience(&req.mfa_token, MFA_PENDING_AUDIENCE) {
+            Ok(claims) => claims,
+            Err(_) => return HttpResponse::Unauthorized().json("Invalid or expired MFA token"),
+        };
+
+    let user = match sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = ?")
+        .bind(&pending.sub)
+        .fetch_optional(db_pool.get_ref())
+        .await
+    {
+        Ok(Some(user)) if user.is_active => user,
+        Ok(_) => return HttpResponse::Unauthorized().json("Invalid or expired MFA token"),
+        Err(e) => {
+            eprintln!("Failed to fetch user: {:?}", e);
+            return HttpResponse::InternalServerError().json("Failed to sign in");
+        }
+    };
+
+    match check_code(&http_req, &db_pool, &config, &user.id, &req.code).await {
+        Ok(CodeCheck::Accepted(attempt)) => {
+            if let Err(e) = attempt.succeed(&db_pool).await {
+                eprintln!("Failed to clear sign-in failures: {:?}", e);
+            }
+            // An MFA token is good for a single session, even if it leaks
+            match use_up_token(&db_pool, &pending.jti, &pending.sub, pending.exp).await {
+                Ok(true) => signed_in(&db_pool, &keys, &config, &user).await,
+                Ok(false) => HttpResponse::Unauthorized().json("Invalid or expired MFA token"),
+                Err(e) => {
+                    eprintln!("Failed to use up MFA token: {:?}", e);
+                    HttpResponse::InternalServerError().json("Failed to sign in")
+                }
+            }
+        }
+        Ok(CodeCheck::Rejected) => {
+            HttpResponse::Unauthorized().json(json!({ "error": "Invalid code" }))
+        }
+        Ok(CodeCheck::Locked(locked_until)) => too_many_attempts(locked_until),
+        Err(e) => {
+            eprintln!("Failed to check second factor: {:?}", e);
+            HttpResponse::InternalServerError().json("Failed to sign in")
+        }
+    }
+}
+
+/// Outcome of checking a second-factor code.
+enum CodeCheck {
+    /// The code was right; the attempt is settled by the caller.
+    Accepted(Attempt),
+    /// The code was wrong and counted as a failed sign-in.
+    Rejected,
+    /// The user's email or the client IP is locked out until then.
+    Locked(DateTime<Utc>),
+}
+
+/// Checks a code from the authenticator app or a recovery code of a user.
+///
+/// Runs through the sign-in throttle of the user's email and the client IP, so codes
+/// cannot be guessed faster than passwords.
+async fn check_code(
+    http_req: &HttpRequest,
+    db_pool: &DatabasePool,
+    config: &AppConfig,
+    user_id: &str,
+    code: &str,
+) -> Result<CodeCheck, sqlx::Error> {
+    let email = sqlx::query_scalar::<_, String>("SELECT email FROM users WHERE id = ?")
+        .bind(user_id)
+        .fetch_one(db_pool)
+        .await?;
+    let throttle = &config.login_throttle;
+    let ip = config
+        .trusted_proxies
+        .client_ip_of(http_req)
+        .map(|ip| ip.to_string());
+
+    let attempt =
+        match begin_attempt(db_pool, throttle, &normalize_email(&email), ip.as_deref()).await? {
+            AttemptStart::Allowed(attempt) => attempt,
+            AttemptStart::Locked(locked_until) => return Ok(CodeCheck::Locked(locked_until)),
+        };
+    tokio::time::sleep(throttle.delay(attempt.prior_failures)).await;
+
+    if verify_second_factor(db_pool, user_id, code).await? {
+        Ok(CodeCheck::Accepted(attempt))
+    } else {
+        attempt.fail(db_pool, throttle).await?;
+        Ok(CodeCheck::Rejected)
+    }
+}
+
+/// Takes back an accepted attempt that did not sign anyone in.
+async fn release(attempt: Attempt, db_pool: &DatabasePool) {
+    if let Err(e) = attempt.release(db_pool).await {
+        eprintln!("Failed to release sign-in attempt: {:?}", e);
+    }
+}
+
+/// The caller's claims, provided they signed in themselves: an API key must not be
+/// able to change the second factor protecting the account.
+fn signed_in_claims(req: &HttpRequest) -> Result<Claims, HttpResponse> {
+    if req.extensions().get::<AuthSource>() == Some(&AuthSource::ApiKey) {
+        return Err(
+            HttpResponse::Forbidden().json("Two-factor settings cannot be changed with an API key")
+        );
+    }
+    req.extensions()
+        .get::<Claims>()
+        .cloned()
+        .ok_or_else(|| HttpResponse::Unauthorized().json("Unauthorized"))
+}
+
+fn invalid_code() -> HttpResponse {
+    let mut errors = ValidationErrors::default();
+    errors.add("code", "Invalid code");
+    HttpResponse::UnprocessableEntity().json(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_url_shortener::generate_uuid;
+    use actix_web::{test, App};
+
+    use super::*;
+
+    /// Needs a MySQL database in `DATABASE_URL`; the migrations are run on it.
+    #[actix_web::test]
+    #[ignore = "needs a MySQL database"]
+    async fn mfa_token_signs_in_only_once() {
+        let db_pool = DatabasePool::connect(&std::env::var("DATABASE_URL").unwrap())
+            .await
+            .unwrap();
+        sqlx::migrate!("./migrations").run(&db_pool).await.unwrap();
+
+        let user = User {
+            username: format!("mfa-{}", &generate_uuid()[..8]),
+            email: format!("{}@example.com", generate_uuid()),
+            password: "!".to_string(),
+            is_active: true,
+            ..User::default()
+        };
+        sqlx::query(
+            "INSERT INTO users (id, username, email, password, is_active, roles) VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&user.id)
+        .bind(&user.username)
+        .bind(&user.email)
+        .bind(&user.password)
+        .bind(user.is_active)
+        .bind(&user.roles)
+        .execute(&db_pool)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO totp_credentials (user_id, secret, enabled_at) VALUES (?, ?, ?)")
+            .bind(&user.id)
+            .bind(generate_secret())
+            .bind(Utc::now())
+            .execute(&db_pool)
+            .await
+            .unwrap();
+        let mut tx = db_pool.begin().await.unwrap();
+        let recovery_codes = replace_recovery_codes(&mut tx, &user.id).await.unwrap();
+        tx.commit().await.unwrap();
+
+        let keys = KeyStore::hmac("test", b"test-secret");
+        let mfa_token = keys
+            .sign(&MfaPendingClaims::new(
+                user.id.clone(),
+                chrono::Duration::minutes(5),
+            ))
+            .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db_pool.clone()))
+                .app_data(Data::new(keys))
+                .app_data(Data::new(AppConfig::default()))
+                .service(verify_mfa),
+        )
+        .await;
+        let verify = |code: &str| {
+            test::TestRequest::post()
+                .uri("/mfa/verify")
+                .set_json(json!({ "mfaToken": mfa_token, "code": code }))
+                .to_request()
+        };
+
+        let first = test::call_service(&app, verify(&recovery_codes[0])).await;
+        assert_eq!(first.status(), 200);
+        // A valid code does not make a used token good again
+        let second = test::call_service(&app, verify(&recovery_codes[1])).await;
+        assert_eq!(second.status(), 401);
+
+        sqlx::query("DELETE FROM users WHERE id = ?")
+            .bind(&user.id)
+            .execute(&db_pool)
+            .await
+            .unwrap();
+    }
+}
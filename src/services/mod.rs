@@ -1,3 +1,10 @@
+pub mod analytics_services;
+pub mod api_key_services;
 pub mod auth_services;
+pub mod lockout_services;
+pub mod metrics_services;
+pub mod mfa_services;
+pub mod oidc_services;
+pub mod token_services;
 pub mod url_services;
 pub mod user_services;
@@ -0,0 +1,369 @@
+use actix_url_shortener::{
+    generate_secure_token, hash_token,
+    validation::{sanitize_username, USERNAME_LENGTH},
+};
+use actix_web::{
+    cookie::{time, Cookie, SameSite},
+    get,
+    http::header::LOCATION,
+    web::{Data, Query},
+    HttpRequest, HttpResponse, Responder,
+};
+use chrono::{Duration, Utc};
+use serde_json::json;
+use sqlx::types::Json;
+
+use crate::{
+    config::AppConfig,
+    database::DatabasePool,
+    keys::KeyStore,
+    mfa,
+    oidc::{Identity, OidcClient, OidcConfig, OidcError},
+    schema::{
+        mfa::MfaPendingClaims,
+        oidc::{OidcCallbackQuery, OidcLoginClaims, OIDC_LOGIN_AUDIENCE},
+        user::User,
+    },
+    services::token_services::{set_session_cookies, start_session},
+};
+
+/// Cookie holding the signed `OidcLoginClaims` of a sign-in in progress.
+const OIDC_LOGIN_COOKIE: &str = "oidc_login";
+
+/// Time the user has to complete the sign-in at the identity provider.
+const OIDC_LOGIN_TTL_MINUTES: i64 = 10;
+
+/// Stored as the password of provisioned users. It is in no supported hash format, so no
+/// password ever matches it and they can only sign in through the provider.
+const UNUSABLE_PASSWORD: &str = "!";
+
+/// Username of provisioned users when neither their provider username nor their email
+/// makes a valid one.
+const FALLBACK_USERNAME: &str = "user";
+
+/// Start single sign-on: redirect the browser to the identity provider.
+#[get("/oidc/login")]
+pub async fn oidc_login(
+    keys: Data<KeyStore>,
+    config: Data<AppConfig>,
+    oidc: Option<Data<OidcClient>>,
+) -> impl Responder {
+    let Some(oidc) = oidc else {
+        return HttpResponse::NotFound().json("Single sign-on is not configured");
+    };
+
+    let login = OidcLoginClaims {
+        aud: OIDC_LOGIN_AUDIENCE.to_string(),
+        exp: (Utc::now() + Duration::minutes(OIDC_LOGIN_TTL_MINUTES)).timestamp() as usize,
+        state: generate_secure_token(16),
+        nonce: generate_secure_token(16),
+        code_verifier: generate_secure_token(32),
+    };
+    let url = match oidc
+        .authorization_url(&login.state, &login.nonce, &login.code_verifier)
+        .await
+    {
+        Ok(url) => url,
+        Err(e) => {
+            eprintln!("Failed to start single sign-on: {}", e);
+            return HttpResponse::BadGateway().json("Identity provider unavailable");
+        }
+    };
+    let cookie = match keys.sign(&login) {
+        Ok(cookie) => cookie,
+        Err(e) => {
+            eprintln!("Failed to sign OIDC login state: {:?}", e);
+            return HttpResponse::InternalServerError().json("Failed to start single sign-on");
+        }
+    };
+
+    HttpResponse::Found()
+        .insert_header((LOCATION, url))
+        .cookie(login_cookie(&config, cookie))
+        .finish()
+}
+
+/// Where the identity provider sends the browser back. Signs the user in, linking
+/// or creating their account on first use, and redirects to the application.
+///
+/// Users with two-factor authentication are not signed in yet: the redirect carries
+/// `#mfaToken=...` instead, to finish signing in with `/auth/mfa/verify`.
+#[get("/oidc/callback")]
+pub async fn oidc_callback(
+    req: HttpRequest,
+    db_pool: Data<DatabasePool>,
+    keys: Data<KeyStore>,
+    config: Data<AppConfig>,
+    oidc: Option<Data<OidcClient>>,
+    query: Query<OidcCallbackQuery>,
+) -> impl Responder {
+    let Some(oidc) = oidc else {
+        return HttpResponse::NotFound().json("Single sign-on is not configured");
+    };
+
+    let user = match finish_sign_in(&req, &db_pool, &keys, &oidc, query.into_inner()).await {
+        Ok(user) => user,
+        Err(mut response) => {
+            let _ = response.add_removal_cookie(&login_cookie(&config, String::new()));
+            return response;
+        }
+    };
+
+    let mut removal = login_cookie(&config, String::new());
+    removal.make_removal();
+
+    // Whatever the provider checked, the second factor set up here is still required
+    let mfa_enabled = match mfa::is_enabled(&db_pool, &user.id).await {
+        Ok(mfa_enabled) => mfa_enabled,
+        Err(e) => {
+            eprintln!("Failed to check two-factor authentication: {:?}", e);
+            return HttpResponse::InternalServerError().json("Failed to sign in");
+        }
+    };
+    if mfa_enabled {
+        let claims = MfaPendingClaims::new(user.id, config.mfa_token_ttl);
+        return match keys.sign(&claims) {
+            // In the fragment, so the token is not sent on to the application's server
+            Ok(mfa_token) => HttpResponse::Found()
+                .insert_header((
+                    LOCATION,
+                    format!(
+                        "{}#mfaToken={}",
+                        oidc.config().post_login_redirect,
+                        mfa_token
+                    ),
+                ))
+                .cookie(removal)
+                .finish(),
+            Err(e) => {
+                eprintln!("Failed to sign MFA token: {:?}", e);
+                HttpResponse::InternalServerError().json("Failed to sign in")
+            }
+        };
+    }
+
+    match start_session(&db_pool, &keys, &config, &user.id, &user.roles).await {
+        Ok(session) => {
+            let mut response = HttpResponse::Found();
+            response
+                .insert_header((LOCATION, oidc.config().post_login_redirect.clone()))
+                .cookie(removal);
+            set_session_cookies(&mut response, &config, &session);
+            response.finish()
+        }
+        Err(e) => {
+            eprintln!("Failed to start session: {}", e);
+            HttpResponse::InternalServerError().json("Failed to start session")
+        }
+    }
+}
+
+/// Checks the callback against the sign-in started in this browser, redeems the code
+/// and finds the user it belongs to.
+async fn finish_sign_in(
+    req: &HttpRequest,
+    db_pool: &DatabasePool,
+    keys: &KeyStore,
+    oidc: &OidcClient,
+    query: OidcCallbackQuery,
+) -> Result<User, HttpResponse> {
+    if let Some(error) = query.error {
+        return Err(HttpResponse::Unauthorized().json(json!({
+            "error": format!(
+                "Sign-in was not completed: {}",
+                query.error_description.unwrap_or(error)
+            )
+        })));
+    }
+    let (Some(code), Some(state)) = (query.code, query.state) else {
+        return Err(HttpResponse::BadRequest().json("Missing code or state"));
+    };
+
+    // The state must match the one this browser was given, or the callback could
+    // sign a victim into an account of the attacker's choosing
+    let login = req
+        .cookie(OIDC_LOGIN_COOKIE)
+        .and_then(|cookie| {
+            keys.verify_for_audience::<OidcLoginClaims>(cookie.value(), OIDC_LOGIN_AUDIENCE)
+                .ok()
+        })
+        .filter(|login| hash_token(&login.state) == hash_token(&state))
+        .ok_or_else(|| {
+            HttpResponse::BadRequest().json("Sign-in expired or was started in another browser")
+        })?;
+
+    let identity = oidc
+        .exchange_code(&code, &login.code_verifier, &login.nonce)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to complete single sign-on: {}", e);
+            match e {
+                OidcError::InvalidIdToken(_) => {
+                    HttpResponse::Unauthorized().json("Identity provider response rejected")
+                }
+                _ => HttpResponse::BadGateway().json("Identity provider unavailable"),
+            }
+        })?;
+
+    let user = match resolve_user(db_pool, oidc.config(), &identity).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return Err(HttpResponse::Forbidden()
+                .json("The identity provider did not supply a verified email address"))
+        }
+        Err(e) => {
+            eprintln!("Failed to resolve single sign-on user: {:?}", e);
+            return Err(HttpResponse::InternalServerError().json("Failed to sign in"));
+        }
+    };
+    if !user.is_active {
+        return Err(HttpResponse::Forbidden().json("Account is not active"));
+    }
+
+    Ok(user)
+}
+
+/// Finds the user linked to the identity. On first sign-in the identity is linked to
+/// the user with the same, provider-verified email, or to a new user. Returns `None`
+/// when the email is missing or unverified.
+///
+/// An account still waiting for its email to be verified is activated by the link, and
+/// its password is dropped: whoever signed up with it never proved to own the address.
+///
+/// With a role mapping configured, the user's roles are updated from their groups
+/// on every sign-in.
+async fn resolve_user(
+    db_pool: &DatabasePool,
+    config: &OidcConfig,
+    identity: &Identity,
+) -> Result<Option<User>, sqlx::Error> {
+    let linked = sqlx::query_as::<_, User>(
+        r#"
+        SELECT u.*
+        FROM users u
+        JOIN user_identities i ON i.user_id = u.id
+        WHERE i.issuer = ? AND i.subject = ?
+        "#,
+    )
+    .bind(&identity.issuer)
+    .bind(&identity.subject)
+    .fetch_optional(db_pool)
+    .await?;
+
+    let mut user = match linked {
+        Some(user) => user,
+        None => {
+            let Some(email) = identity.email.as_ref().filter(|_| identity.email_verified) else {
+                return Ok(None);
+            };
+
+            let mut tx = db_pool.begin().await?;
+            let existing =
+                sqlx::query_as::<_, User>("SELECT * FROM users WHERE email = ? FOR UPDATE")
+                    .bind(email)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+            let user = match existing {
+                Some(mut user) if !user.is_active => {
+                    sqlx::query("UPDATE users SET is_active = TRUE, password = ? WHERE id = ?")
+                        .bind(UNUSABLE_PASSWORD)
+                        .bind(&user.id)
+                        .execute(&mut *tx)
+                        .await?;
+                    user.is_active = true;
+                    user.password = UNUSABLE_PASSWORD.to_string();
+                    user
+                }
+                Some(user) => user,
+                None => {
+                    let mut user = User {
+                        email: email.clone(),
+                        password: UNUSABLE_PASSWORD.to_string(),
+                        is_active: true, // The provider verified the email
+                        roles: Json(config.role_mapping.roles_for(&identity.groups)),
+                        ..User::default()
+                    };
+                    // Provider names may be anything; only valid usernames are stored
+                    let username = identity
+                        .preferred_username
+                        .as_deref()
+                        .and_then(sanitize_username)
+                        .or_else(|| sanitize_username(email.split('@').next().unwrap_or(email)))
+                        .unwrap_or_else(|| FALLBACK_USERNAME.to_string());
+                    let taken = sqlx::query_scalar::<_, i64>(
+                        "SELECT EXISTS(SELECT 1 FROM users WHERE username = ?)",
+                    )
+                    .bind(&username)
+                    .fetch_one(&mut *tx)
+                    .await?
+                        != 0;
+                    user.username = if taken {
+                        // Shortened to leave room for the suffix
+                        let suffix = format!("-{}", generate_secure_token(3));
+                        let length = username.len().min(USERNAME_LENGTH.end() - suffix.len());
+                        format!("{}{}", &username[..length], suffix)
+                    } else {
+                        username
+                    };
+
+                    sqlx::query(
+                        r#"
+                        INSERT INTO users (id, username, email, password, is_active, roles)
+                        VALUES (?, ?, ?, ?, ?, ?)
+                        "#,
+                    )
+                    .bind(&user.id)
+                    .bind(&user.username)
+                    .bind(&user.email)
+                    .bind(&user.password)
+                    .bind(user.is_active)
+                    .bind(&user.roles)
+                    .execute(&mut *tx)
+                    .await?;
+                    user
+                }
+            };
+            sqlx::query("INSERT INTO user_identities (user_id, issuer, subject) VALUES (?, ?, ?)")
+                .bind(&user.id)
+                .bind(&identity.issuer)
+                .bind(&identity.subject)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            user
+        }
+    };
+
+    sqlx::query("UPDATE user_identities SET last_login_at = ? WHERE issuer = ? AND subject = ?")
+        .bind(Utc::now())
+        .bind(&identity.issuer)
+        .bind(&identity.subject)
+        .execute(db_pool)
+        .await?;
+
+    if !config.role_mapping.is_empty() {
+        let roles = config.role_mapping.roles_for(&identity.groups);
+        if roles != user.roles.0 {
+            sqlx::query("UPDATE users SET roles = ? WHERE id = ?")
+                .bind(Json(&roles))
+                .bind(&user.id)
+                .execute(db_pool)
+                .await?;
+            user.roles = Json(roles);
+        }
+    }
+
+    Ok(Some(user))
+}
+
+/// `SameSite=Lax` whatever the session cookies use: the provider's redirect back is a
+/// cross-site navigation, which `Strict` cookies would not be sent with.
+fn login_cookie(config: &AppConfig, value: String) -> Cookie<'static> {
+    Cookie::build(OIDC_LOGIN_COOKIE, value)
+        .path("/auth/oidc")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .secure(config.cookie_secure)
+        .max_age(time::Duration::minutes(OIDC_LOGIN_TTL_MINUTES))
+        .finish()
+}
@@ -0,0 +1,392 @@
+use std::fmt;
+
+use actix_url_shortener::{generate_secure_token, generate_uuid, hash_token};
+use actix_web::{
+    cookie::{time, Cookie},
+    HttpResponseBuilder,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use sqlx::{Executor, MySql, Transaction};
+
+use crate::{
+    config::AppConfig,
+    database::DatabasePool,
+    keys::KeyStore,
+    schema::auth::{Claims, TokenPurpose},
+};
+
+pub const ACCESS_TOKEN_COOKIE: &str = "access_token";
+pub const REFRESH_TOKEN_COOKIE: &str = "refresh_token";
+pub const CSRF_TOKEN_COOKIE: &str = "csrf_token";
+
+/// The refresh cookie is only sent to the auth endpoints.
+const REFRESH_TOKEN_PATH: &str = "/auth";
+
+/// Failure while issuing tokens.
+#[derive(Debug)]
+pub enum TokenError {
+    Jwt(jsonwebtoken::errors::Error),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenError::Jwt(err) => write!(f, "failed to sign token: {}", err),
+            TokenError::Database(err) => write!(f, "failed to store token: {}", err),
+        }
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for TokenError {
+    fn from(err: jsonwebtoken::errors::Error) -> Self {
+        TokenError::Jwt(err)
+    }
+}
+
+impl From<sqlx::Error> for TokenError {
+    fn from(err: sqlx::Error) -> Self {
+        TokenError::Database(err)
+    }
+}
+
+/// A freshly issued access/refresh token pair.
+pub struct Session {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+/// Signs a short-lived access token for a user.
+pub fn encode_access_token(
+    keys: &KeyStore,
+    config: &AppConfig,
+    user_id: &str,
+    roles: &[String],
+) -> Result<String, jsonwebtoken::errors::Error> {
+    keys.sign(&Claims::new(
+        user_id.to_string(),
+        roles.to_vec(),
+        config.access_token_ttl,
+    ))
+}
+
+/// Verifies an access token's signature and expiry and returns its claims.
+pub fn decode_access_token(
+    keys: &KeyStore,
+    token: &str,
+) -> Result<Claims, jsonwebtoken::errors::Error> {
+    keys.verify(token)
+}
+
+/// Whether a correctly signed, unexpired access token may still be used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenStatus {
+    Valid,
+    /// Signed out, issued before the user's last password change, or the user was deleted.
+    Revoked,
+    /// The user has not verified their email address or was deactivated.
+    InactiveUser,
+}
+
+/// Checks an access token against the server-side state of its user.
+pub async fn access_token_status(
+    db_pool: &DatabasePool,
+    claims: &Claims,
+) -> Result<TokenStatus, sqlx::Error> {
+    let row: Option<(Option<DateTime<Utc>>, bool, i64)> = sqlx::query_as(
+        r#"
+        SELECT tokens_invalid_before, is_active,
+               EXISTS(SELECT 1 FROM revoked_tokens WHERE jti = ?) AS revoked
+        FROM users
+        WHERE id = ?
+        "#,
+    )
+    .bind(&claims.jti)
+    .bind(&claims.sub)
+    .fetch_optional(db_pool)
+    .await?;
+
+    Ok(match row {
+        None => TokenStatus::Revoked, // The user was deleted
+        Some((_, _, revoked)) if revoked != 0 => TokenStatus::Revoked,
+        // Issued in the same millisecond as the invalidation counts as before it
+        Some((Some(invalid_before), _, _)) if claims.issued_at() <= invalid_before => {
+            TokenStatus::Revoked
+        }
+        Some((_, false, _)) => TokenStatus::InactiveUser,
+        Some(_) => TokenStatus::Valid,
+    })
+}
+
+/// Adds an access token to the revocation list until it expires on its own.
+///
+/// Entries whose token has expired anyway are pruned at the same time.
+pub async fn revoke_access_token(
+    db_pool: &DatabasePool,
+    claims: &Claims,
+) -> Result<(), sqlx::Error> {
+    revoke_token_id(db_pool, &claims.jti, &claims.sub, claims.exp).await?;
+    Ok(())
+}
+
+/// Uses up a single-use token, such as the `mfaToken` of a two-factor sign-in, by
+/// adding its `jti` to the revocation list. Returns `false` when it was used before.
+pub async fn use_up_token(
+    db_pool: &DatabasePool,
+    jti: &str,
+    user_id: &str,
+    exp: usize,
+) -> Result<bool, sqlx::Error> {
+    revoke_token_id(db_pool, jti, user_id, exp).await
+}
+
+/// Records `jti` as revoked until `exp`; `false` when it already was.
+async fn revoke_token_id(
+    db_pool: &DatabasePool,
+    jti: &str,
+    user_id: &str,
+    exp: usize,
+) -> Result<bool, sqlx::Error> {
+    let expires_at = Utc
+        .timestamp_opt(exp as i64, 0)
+        .single()
+        .unwrap_or_else(Utc::now);
+
+    sqlx::query("DELETE FROM revoked_tokens WHERE expires_at < ?")
+        .bind(Utc::now())
+        .execute(db_pool)
+        .await?;
+    let result = sqlx::query(
+        "INSERT IGNORE INTO revoked_tokens (jti, user_id, expires_at) VALUES (?, ?, ?)",
+    )
+    .bind(jti)
+    .bind(user_id)
+    .bind(expires_at)
+    .execute(db_pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Revokes a single refresh token, given its plaintext value.
+pub async fn revoke_refresh_token(db_pool: &DatabasePool, token: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE refresh_tokens SET revoked_at = ? WHERE token_hash = ? AND revoked_at IS NULL",
+    )
+    .bind(Utc::now())
+    .bind(hash_token(token))
+    .execute(db_pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Stores a new refresh token in `family_id` and returns the token and its row id.
+pub async fn issue_refresh_token<'c, E>(
+    executor: E,
+    config: &AppConfig,
+    user_id: &str,
+    family_id: &str,
+) -> Result<(String, String), sqlx::Error>
+where
+    E: Executor<'c, Database = MySql>,
+{
+    let id = generate_uuid();
+    let token = generate_secure_token(32);
+
+    sqlx::query(
+        r#"
+        INSERT INTO refresh_tokens (id, user_id, family_id, token_hash, expires_at)
+        VALUES (?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(&id)
+    .bind(user_id)
+    .bind(family_id)
+    .bind(hash_token(&token))
+    .bind(Utc::now() + config.refresh_token_ttl)
+    .execute(executor)
+    .await?;
+
+    Ok((token, id))
+}
+
+/// Starts a new login session: an access token plus the first refresh token of a new family.
+pub async fn start_session(
+    db_pool: &DatabasePool,
+    keys: &KeyStore,
+    config: &AppConfig,
+    user_id: &str,
+    roles: &[String],
+) -> Result<Session, TokenError> {
+    let access_token = encode_access_token(keys, config, user_id, roles)?;
+    let (refresh_token, _) =
+        issue_refresh_token(db_pool, config, user_id, &generate_uuid()).await?;
+
+    Ok(Session {
+        access_token,
+        refresh_token,
+    })
+}
+
+/// Revokes every refresh token of a user, ending all of their sessions.
+pub async fn revoke_user_refresh_tokens<'c, E>(
+    executor: E,
+    user_id: &str,
+) -> Result<(), sqlx::Error>
+where
+    E: Executor<'c, Database = MySql>,
+{
+    sqlx::query(
+        "UPDATE refresh_tokens SET revoked_at = ? WHERE user_id = ? AND revoked_at IS NULL",
+    )
+    .bind(Utc::now())
+    .bind(user_id)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Invalidates every access token issued to a user so far.
+pub async fn invalidate_access_tokens<'c, E>(executor: E, user_id: &str) -> Result<(), sqlx::Error>
+where
+    E: Executor<'c, Database = MySql>,
+{
+    sqlx::query("UPDATE users SET tokens_invalid_before = ? WHERE id = ?")
+        .bind(Utc::now())
+        .bind(user_id)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+/// Issues a single-use token for `purpose`, replacing the user's unused ones.
+pub async fn issue_user_token(
+    tx: &mut Transaction<'_, MySql>,
+    user_id: &str,
+    purpose: TokenPurpose,
+    ttl: chrono::Duration,
+) -> Result<String, sqlx::Error> {
+    let now = Utc::now();
+    sqlx::query(
+        "UPDATE user_tokens SET used_at = ? WHERE user_id = ? AND purpose = ? AND used_at IS NULL",
+    )
+    .bind(now)
+    .bind(user_id)
+    .bind(purpose.as_str())
+    .execute(&mut **tx)
+    .await?;
+
+    let token = generate_secure_token(32);
+    sqlx::query(
+        r#"
+        INSERT INTO user_tokens (id, user_id, purpose, token_hash, expires_at)
+        VALUES (?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(generate_uuid())
+    .bind(user_id)
+    .bind(purpose.as_str())
+    .bind(hash_token(&token))
+    .bind(now + ttl)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(token)
+}
+
+/// Marks a single-use token as used and returns its user, or `None` when the token is
+/// unknown, meant for something else, expired or already used.
+pub async fn consume_user_token(
+    tx: &mut Transaction<'_, MySql>,
+    token: &str,
+    purpose: TokenPurpose,
+) -> Result<Option<String>, sqlx::Error> {
+    let now = Utc::now();
+    let Some((id, user_id)) = sqlx::query_as::<_, (String, String)>(
+        r#"
+        SELECT id, user_id
+        FROM user_tokens
+        WHERE token_hash = ? AND purpose = ? AND used_at IS NULL AND expires_at > ?
+        FOR UPDATE
+        "#,
+    )
+    .bind(hash_token(token))
+    .bind(purpose.as_str())
+    .bind(now)
+    .fetch_optional(&mut **tx)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    sqlx::query("UPDATE user_tokens SET used_at = ? WHERE id = ?")
+        .bind(now)
+        .bind(id)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(Some(user_id))
+}
+
+/// Sets the access, refresh and CSRF cookies for a session on a response.
+///
+/// The CSRF cookie is readable by scripts so the client can echo it back in the
+/// `X-CSRF-Token` header (double-submit).
+pub fn set_session_cookies(
+    response: &mut HttpResponseBuilder,
+    config: &AppConfig,
+    session: &Session,
+) {
+    let mut refresh = session_cookie(
+        config,
+        REFRESH_TOKEN_COOKIE,
+        session.refresh_token.clone(),
+        REFRESH_TOKEN_PATH,
+    );
+    refresh.set_max_age(time::Duration::seconds(
+        config.refresh_token_ttl.num_seconds(),
+    ));
+    let mut csrf = session_cookie(config, CSRF_TOKEN_COOKIE, generate_secure_token(32), "/");
+    csrf.set_http_only(false);
+
+    response
+        .cookie(session_cookie(
+            config,
+            ACCESS_TOKEN_COOKIE,
+            session.access_token.clone(),
+            "/",
+        ))
+        .cookie(refresh)
+        .cookie(csrf);
+}
+
+/// Expires every session cookie on a response.
+pub fn clear_session_cookies(response: &mut HttpResponseBuilder, config: &AppConfig) {
+    for (name, path) in [
+        (ACCESS_TOKEN_COOKIE, "/"),
+        (REFRESH_TOKEN_COOKIE, REFRESH_TOKEN_PATH),
+        (CSRF_TOKEN_COOKIE, "/"),
+    ] {
+        let mut cookie = session_cookie(config, name, String::new(), path);
+        cookie.make_removal();
+        response.cookie(cookie);
+    }
+}
+
+/// An HttpOnly cookie carrying the configured `SameSite` and `Secure` attributes.
+fn session_cookie(
+    config: &AppConfig,
+    name: &'static str,
+    value: String,
+    path: &'static str,
+) -> Cookie<'static> {
+    Cookie::build(name, value)
+        .path(path)
+        .http_only(true)
+        .same_site(config.cookie_same_site)
+        .secure(config.cookie_secure)
+        .finish()
+}
@@ -1,60 +1,280 @@
 use crate::{
+    cache::RedirectCache,
+    clicks::ClickCounter,
+    config::{AppConfig, ExpiredLinkBehavior},
     database::DatabasePool,
+    policy::{authorize, Action, Resource},
     schema::{
-        auth::Claims,
-        url::{CreateUrlRequest, ShortUrl, UpdateUrlRequest},
+        analytics::ClickEvent,
+        url::{CreateUrlRequest, RedirectType, ShortUrl, UpdateUrlRequest},
     },
 };
-use actix_url_shortener::generate_short_code_from_url;
+use actix_url_shortener::{
+    short_code::ShortCodeGenerator,
+    validate_alias,
+    validation::{normalize_destination_url, UrlPolicy, ValidationErrors},
+};
 use actix_web::{
-    delete, get, post, put,
+    delete, get,
+    http::{Method, StatusCode},
+    post, put, route,
     web::{Data, Json, Path},
-    HttpMessage, HttpRequest, HttpResponse, Responder,
+    HttpRequest, HttpResponse, Responder,
 };
-use sqlx::Row; // Import the Row trait to use `get`
+use chrono::{Duration, Utc};
+use sqlx::Row;
+use std::future::Future; // Import the Row trait to use `get`
 
 #[post("/")]
 pub async fn create_short_url(
     req: HttpRequest,
     body: Json<CreateUrlRequest>,
     db: Data<DatabasePool>,
+    config: Data<AppConfig>,
+    generator: Data<dyn ShortCodeGenerator>,
+    cache: Data<RedirectCache>,
 ) -> impl Responder {
-    // Extract Claims from the request extensions
-    let req = req.extensions();
-    let claims = req.get::<Claims>();
+    // Only callers allowed to create links get past this point
+    let claims = match authorize(&req, Action::Create, Resource::Url { owner: None }) {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    // Extract the data from the incoming request body
+    let CreateUrlRequest {
+        original_url,
+        expiration,
+        expired_redirect_url,
+        alias,
+        redirect_type,
+    } = body.into_inner();
+
+    // Validate every field up front so the client sees all problems at once
+    let mut errors = ValidationErrors::default();
+    let original_url =
+        normalize_field(&mut errors, "originalUrl", original_url, &config.url_policy);
+    let expired_redirect_url = expired_redirect_url
+        .map(|url| normalize_field(&mut errors, "expiredRedirectUrl", url, &config.url_policy));
+    if let Some(alias) = &alias {
+        if let Err(reason) = validate_alias(alias) {
+            errors.add("alias", reason);
+        }
+    }
+    if !errors.is_empty() {
+        return HttpResponse::UnprocessableEntity().json(errors);
+    }
+
+    // Links to our own short URLs are checked for loops and flattened when too deep
+    let original_url = match resolve_redirect_chain(&db, &config, None, original_url).await {
+        Ok(original_url) => original_url,
+        Err(err) => return err.into_response("originalUrl"),
+    };
+    let expired_redirect_url = match expired_redirect_url {
+        Some(url) => match resolve_redirect_chain(&db, &config, None, url).await {
+            Ok(url) => Some(url),
+            Err(err) => return err.into_response("expiredRedirectUrl"),
+        },
+        None => None,
+    };
 
-    if let Some(claims) = claims {
-        // Extract the data from the incoming request body
-        let CreateUrlRequest { original_url } = body.into_inner();
-        let short_code = generate_short_code_from_url(&original_url, 10);
+    let short_url = ShortUrl::default();
+    let user_id = claims.sub.clone(); // Extract user_id from the 'sub' field of claims
 
-        let short_url = ShortUrl::default();
-        let user_id = claims.sub.clone(); // Extract user_id from the 'sub' field of claims
+    // Create a new ShortUrl in the database
+    let query = r#"
+    INSERT INTO short_urls (id, original_url, short_code, created_at, expiration, expired_redirect_url, redirect_type, user_id)
+    VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+    "#;
 
-        // Create a new ShortUrl in the database
-        let query = r#"
-        INSERT INTO short_urls (id, original_url, short_code, created_at, user_id)
-        VALUES (?, ?, ?, ?, ?)
-        "#;
+    // Generated codes are retried on collision; a taken alias is reported as a conflict
+    for attempt in 0..config.short_code_max_attempts {
+        let short_code = alias
+            .clone()
+            .unwrap_or_else(|| generator.generate(&original_url, attempt));
+
+        // A replaced code still in its grace period belongs to its old link
+        match alias_in_use(db.as_ref(), &short_code).await {
+            Ok(false) => {}
+            Ok(true) if alias.is_some() => {
+                return HttpResponse::Conflict().json("Short code is already taken")
+            }
+            Ok(true) => continue,
+            Err(err) => {
+                eprintln!("Error checking short code aliases: {}", err);
+                return HttpResponse::InternalServerError().json("Internal Server Error");
+            }
+        }
 
         match sqlx::query(query)
-            .bind(short_url.id)
-            .bind(original_url)
-            .bind(short_code)
+            .bind(&short_url.id)
+            .bind(&original_url)
+            .bind(&short_code)
             .bind(short_url.created_at)
-            .bind(user_id)
+            .bind(expiration)
+            .bind(&expired_redirect_url)
+            .bind(redirect_type)
+            .bind(&user_id)
             .execute(db.as_ref()) // Execute the query with the DB pool
             .await
         {
-            Ok(_) => HttpResponse::Created().json("Short URL created successfully"), // Return success
+            Ok(_) => {
+                cache.invalidate(&short_code); // Drop a cached "not found" for this code
+                return HttpResponse::Created().json("Short URL created successfully");
+                // Return success
+            }
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                if alias.is_some() {
+                    return HttpResponse::Conflict().json("Short code is already taken");
+                }
+            }
             Err(err) => {
                 eprintln!(" Error creating short URL: {}", err);
-                HttpResponse::InternalServerError().json("Internal Server Error")
+                return HttpResponse::InternalServerError().json("Internal Server Error");
                 // Return error if failed
             }
         }
+    }
+
+    eprintln!(
+        "Error creating short URL: no free short code after {} attempts",
+        config.short_code_max_attempts
+    );
+    HttpResponse::InternalServerError().json("Internal Server Error")
+}
+
+/// Normalizes a destination URL, recording a field error when it is invalid.
+fn normalize_field(
+    errors: &mut ValidationErrors,
+    field: &str,
+    url: String,
+    policy: &UrlPolicy,
+) -> String {
+    normalize_destination_url(&url, policy).unwrap_or_else(|message| {
+        errors.add(field, message);
+        url
+    })
+}
+
+/// Why a destination pointing back at this service was refused.
+enum ChainError {
+    Invalid(String),
+    Database(sqlx::Error),
+}
+
+impl ChainError {
+    fn into_response(self, field: &str) -> HttpResponse {
+        match self {
+            ChainError::Invalid(message) => {
+                let mut errors = ValidationErrors::default();
+                errors.add(field, message);
+                HttpResponse::UnprocessableEntity().json(errors)
+            }
+            ChainError::Database(err) => {
+                eprintln!("Error resolving short link chain: {}", err);
+                HttpResponse::InternalServerError().json("Internal Server Error")
+            }
+        }
+    }
+}
+
+/// Upper bound on hops followed while resolving a chain, whatever the configured depth.
+const MAX_CHAIN_HOPS: usize = 32;
+
+/// Follows a destination through this service's own short links.
+///
+/// External destinations are returned unchanged. Expired links are followed to
+/// their `expired_redirect_url`. Chains that come back to `url_id` or revisit a
+/// link are rejected; chains longer than `max_redirect_chain_depth` are replaced
+/// by their final destination.
+async fn resolve_redirect_chain(
+    db_pool: &DatabasePool,
+    config: &AppConfig,
+    url_id: Option<&str>,
+    destination: String,
+) -> Result<String, ChainError> {
+    walk_redirect_chain(config, url_id, destination, |short_code| async move {
+        let url = find_by_short_code(db_pool, &short_code).await?;
+        Ok(url.map(chain_hop))
+    })
+    .await
+}
+
+/// The id of `url` and where it currently sends visitors: its fallback once
+/// expired, or nowhere if it has none.
+fn chain_hop(url: ShortUrl) -> (String, Option<String>) {
+    let next = if url.is_expired() {
+        url.expired_redirect_url
+    } else {
+        Some(url.original_url)
+    };
+    (url.id, next)
+}
+
+/// The walk behind [`resolve_redirect_chain`]. `lookup` maps a short code to the id
+/// of its link and where the link currently sends visitors, if anywhere.
+async fn walk_redirect_chain<F, Fut>(
+    config: &AppConfig,
+    url_id: Option<&str>,
+    destination: String,
+    mut lookup: F,
+) -> Result<String, ChainError>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<Option<(String, Option<String>)>, sqlx::Error>>,
+{
+    let mut visited: Vec<String> = url_id.into_iter().map(str::to_string).collect();
+    let mut current = destination.clone();
+
+    while let Some(short_code) = own_short_code(&current, config)? {
+        if visited.len() > MAX_CHAIN_HOPS {
+            return Err(ChainError::Invalid(
+                "URL goes through too many short links".to_string(),
+            ));
+        }
+
+        let (id, next) = lookup(short_code.clone())
+            .await
+            .map_err(ChainError::Database)?
+            .ok_or_else(|| {
+                ChainError::Invalid(format!("URL points to unknown short link '{}'", short_code))
+            })?;
+
+        if visited.contains(&id) {
+            return Err(ChainError::Invalid(
+                "URL would create a redirect loop".to_string(),
+            ));
+        }
+        visited.push(id);
+
+        // An expired link without a fallback ends the chain
+        match next {
+            Some(next) => current = next,
+            None => break,
+        }
+    }
+
+    let hops = visited.len() - usize::from(url_id.is_some());
+    if hops > config.max_redirect_chain_depth {
+        Ok(current)
     } else {
-        HttpResponse::Unauthorized().json("Unauthorized")
+        Ok(destination)
+    }
+}
+
+/// Returns the short code if `url` is one of this service's `/s/{code}` links.
+fn own_short_code(url: &str, config: &AppConfig) -> Result<Option<String>, ChainError> {
+    let Ok(url) = url::Url::parse(url) else {
+        return Ok(None);
+    };
+    if !config.is_public_host(&url) {
+        return Ok(None);
+    }
+
+    match url.path().strip_prefix("/s/") {
+        Some(code) if !code.is_empty() && !code.contains('/') => Ok(Some(code.to_string())),
+        _ => Err(ChainError::Invalid(
+            "URL must not point at this service, except for /s/ short links".to_string(),
+        )),
     }
 }
 
@@ -65,47 +285,86 @@ pub async fn update_url(
     url_id: Path<String>,                // Extract URL ID from the path
     update_data: Json<UpdateUrlRequest>, // The incoming request body containing the update data
     db_pool: Data<DatabasePool>,         // Shared database connection pool
+    config: Data<AppConfig>,
+    generator: Data<dyn ShortCodeGenerator>,
+    cache: Data<RedirectCache>,
 ) -> impl Responder {
     let url_id = url_id.into_inner(); // Extract the URL ID from the path
-    let update_data = update_data.into_inner(); // Extract the update data from the request body
+    let mut update_data = update_data.into_inner(); // Extract the update data from the request body
 
-    let req = req.extensions(); // Extract Claims from the request extensions
-    let claims = req.get::<Claims>();
+    // Validate and normalize the destination fields before touching the database
+    let mut errors = ValidationErrors::default();
+    if update_data.is_empty() {
+        errors.add("body", "No fields to update");
+        return HttpResponse::UnprocessableEntity().json(errors);
+    }
+    update_data.original_url = update_data
+        .original_url
+        .map(|url| normalize_field(&mut errors, "original_url", url, &config.url_policy));
+    update_data.expired_redirect_url = update_data
+        .expired_redirect_url
+        .map(|url| normalize_field(&mut errors, "expired_redirect_url", url, &config.url_policy));
+    if !errors.is_empty() {
+        return HttpResponse::UnprocessableEntity().json(errors);
+    }
 
-    // Check if the user_id from claims matches the user_id for the URL in the database
-    let user_check_query = "SELECT user_id FROM short_urls WHERE id = ?";
-    match sqlx::query(user_check_query)
+    // Check that the caller may update this URL
+    let user_check_query = "SELECT user_id, short_code, original_url FROM short_urls WHERE id = ?";
+    let (current_code, current_url) = match sqlx::query(user_check_query)
         .bind(&url_id) // Bind the URL ID
         .fetch_one(db_pool.get_ref()) // Execute the query
         .await
     {
         Ok(record) => {
-            let db_user_id: String = record.get::<String, _>("user_id"); // Extract user_id from the query result
-            if let Some(claims) = claims {
-                if db_user_id != claims.sub {
-                    return HttpResponse::Unauthorized().json("Unauthorized to update this URL");
-                    // User not authorized to update
-                }
+            let owner: Option<String> = record.get("user_id"); // Extract user_id from the query result
+            let resource = Resource::Url {
+                owner: owner.as_deref(),
+            };
+            if let Err(response) = authorize(&req, Action::Update, resource) {
+                return response;
             }
+            (
+                record.get::<String, _>("short_code"),
+                record.get::<String, _>("original_url"),
+            )
         }
+        Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().json("URL not found"),
         Err(err) => {
             eprintln!("Error fetching URL user_id: {}", err); // Log the error to the console
             return HttpResponse::InternalServerError().json("Internal Server Error");
             // Return a 500 status if the query fails
         }
+    };
+
+    // Links to our own short URLs are checked for loops and flattened when too deep
+    if let Some(original_url) = update_data.original_url.take() {
+        match resolve_redirect_chain(&db_pool, &config, Some(&url_id), original_url).await {
+            Ok(original_url) => update_data.original_url = Some(original_url),
+            Err(err) => return err.into_response("original_url"),
+        }
+    }
+    if let Some(expired_redirect_url) = update_data.expired_redirect_url.take() {
+        match resolve_redirect_chain(&db_pool, &config, Some(&url_id), expired_redirect_url).await {
+            Ok(url) => update_data.expired_redirect_url = Some(url),
+            Err(err) => return err.into_response("expired_redirect_url"),
+        }
     }
 
     // Build the SQL query dynamically based on the fields that are provided
     let mut query = String::from("UPDATE short_urls SET ");
     let mut params = vec![];
+    let mut short_code_param = None; // Position of the generated short code, re-rolled on collision
 
-    // Check if original_url is provided for update
+    // The short code is kept when the destination changes unless a new one is explicitly requested
+    let destination = update_data.original_url.clone().unwrap_or(current_url);
     if let Some(original_url) = update_data.original_url {
-        // Generate new short code if original_url is updated
-        let short_code = generate_short_code_from_url(&original_url, 10); // Length of short_code (e.g., 10)
-        query.push_str("original_url = ?, short_code = ?, ");
+        query.push_str("original_url = ?, ");
         params.push(original_url);
-        params.push(short_code);
+    }
+    if update_data.regenerate_code {
+        query.push_str("short_code = ?, ");
+        short_code_param = Some(params.len());
+        params.push(String::new());
     }
 
     // Update expiration if provided
@@ -114,6 +373,18 @@ pub async fn update_url(
         params.push(expiration.to_rfc3339()); // Convert to RFC 3339 string format
     }
 
+    // Update the expired-link fallback if provided
+    if let Some(expired_redirect_url) = update_data.expired_redirect_url {
+        query.push_str("expired_redirect_url = ?, ");
+        params.push(expired_redirect_url);
+    }
+
+    // Update the redirect status if provided
+    if let Some(redirect_type) = update_data.redirect_type {
+        query.push_str("redirect_type = ?, ");
+        params.push(u16::from(redirect_type).to_string());
+    }
+
     // Remove the trailing comma and space from the query
     query.pop();
     query.pop();
@@ -122,19 +393,132 @@ pub async fn update_url(
     query.push_str(" WHERE id = ?");
     params.push(url_id);
 
+    for attempt in 0..config.short_code_max_attempts {
+        if let Some(index) = short_code_param {
+            params[index] = generator.generate(&destination, attempt);
+        }
+
+        match apply_url_update(
+            db_pool.get_ref(),
+            &query,
+            &params,
+            short_code_param.map(|index| (current_code.as_str(), params[index].as_str())),
+            config.code_alias_grace_period,
+        )
+        .await
+        {
+            Ok(false) => {} // The new code is a live alias of some link, try another
+            Ok(true) => {
+                // Evict the new code, the old one and any aliases so redirects pick up the change.
+                // Without a grace period the old code has no alias row, so evict it by name
+                cache.invalidate(&current_code);
+                invalidate_cached_codes(db_pool.get_ref(), &cache, params.last().unwrap()).await;
+                return HttpResponse::Ok().json("URL updated successfully");
+                // Return a 200 OK status if successful
+            }
+            Err(sqlx::Error::Database(e))
+                if e.is_unique_violation() && short_code_param.is_some() => {}
+            Err(err) => {
+                eprintln!("Error updating URL: {}", err); // Log the error to the console
+                return HttpResponse::InternalServerError().json("Failed to update URL");
+                // Return a 500 status if the query fails
+            }
+        }
+    }
+
+    eprintln!(
+        "Error updating URL: no free short code after {} attempts",
+        config.short_code_max_attempts
+    );
+    HttpResponse::InternalServerError().json("Failed to update URL")
+}
+
+/// Runs the update and, when the short code changes from `old_code` to `new_code`,
+/// keeps `old_code` redirecting to the same link for `grace_period`. Both happen in
+/// one transaction.
+///
+/// Returns `false` without updating when `new_code` is a live alias.
+async fn apply_url_update(
+    db_pool: &DatabasePool,
+    query: &str,
+    params: &[String],
+    codes: Option<(&str, &str)>,
+    grace_period: Duration,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+
+    if let Some((_, new_code)) = codes {
+        if alias_in_use(&mut *tx, new_code).await? {
+            return Ok(false);
+        }
+    }
+
     // Execute the query, binding each parameter individually
-    let mut query = sqlx::query(&query);
+    let mut update = sqlx::query(query);
     for param in params {
-        query = query.bind(param);
+        update = update.bind(param);
     }
+    update.execute(&mut *tx).await?;
 
-    // Execute the query
-    match query.execute(db_pool.get_ref()).await {
-        Ok(_) => HttpResponse::Ok().json("URL updated successfully"), // Return a 200 OK status if successful
-        Err(err) => {
-            eprintln!("Error updating URL: {}", err); // Log the error to the console
-            HttpResponse::InternalServerError().json("Failed to update URL") // Return a 500 status if the query fails
-        }
+    if let Some((old_code, _)) = codes.filter(|_| grace_period > Duration::zero()) {
+        let url_id = params.last().expect("the URL id is always bound last");
+        sqlx::query(
+            r#"
+            INSERT INTO short_url_aliases (short_code, short_url_id, expires_at)
+            VALUES (?, ?, ?)
+            ON DUPLICATE KEY UPDATE short_url_id = VALUES(short_url_id), expires_at = VALUES(expires_at)
+            "#,
+        )
+        .bind(old_code)
+        .bind(url_id)
+        .bind(Utc::now() + grace_period)
+        .execute(&mut *tx)
+        .await?;
+
+        // Drop aliases whose grace period is over while we are here
+        sqlx::query("DELETE FROM short_url_aliases WHERE expires_at <= ?")
+            .bind(Utc::now())
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Whether `short_code` is an alias still within its grace period. Such a code keeps
+/// redirecting to its old link, so no other link may take it until it expires.
+async fn alias_in_use<'e, E>(executor: E, short_code: &str) -> Result<bool, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = sqlx::MySql>,
+{
+    let in_use = sqlx::query_scalar::<_, i64>(
+        "SELECT EXISTS(SELECT 1 FROM short_url_aliases WHERE short_code = ? AND expires_at > ?)",
+    )
+    .bind(short_code)
+    .bind(Utc::now())
+    .fetch_one(executor)
+    .await?;
+    Ok(in_use != 0)
+}
+
+/// Evicts the short code of a URL and all of its grace-period aliases from the redirect cache.
+async fn invalidate_cached_codes(db_pool: &DatabasePool, cache: &RedirectCache, url_id: &str) {
+    let codes = sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT short_code FROM short_urls WHERE id = ?
+        UNION
+        SELECT short_code FROM short_url_aliases WHERE short_url_id = ?
+        "#,
+    )
+    .bind(url_id)
+    .bind(url_id)
+    .fetch_all(db_pool)
+    .await;
+
+    match codes {
+        Ok(codes) => codes.iter().for_each(|code| cache.invalidate(code)),
+        Err(err) => eprintln!("Failed to look up short codes to evict: {}", err),
     }
 }
 
@@ -144,10 +528,11 @@ pub async fn delete_url(
     req: HttpRequest,
     url_id: Path<String>,        // Extract the URL ID from the path
     db_pool: Data<DatabasePool>, // Shared database connection pool
+    cache: Data<RedirectCache>,
 ) -> impl Responder {
     let url_id = url_id.into_inner(); // Extract the URL ID from the path
 
-    // Check if the user_id from claims matches the user_id for the URL in the database
+    // Check that the caller may delete this URL
     let user_check_query = "SELECT user_id FROM short_urls WHERE id = ?";
     match sqlx::query(user_check_query)
         .bind(&url_id) // Bind the URL ID
@@ -155,16 +540,15 @@ pub async fn delete_url(
         .await
     {
         Ok(record) => {
-            let req = req.extensions(); // Extract Claims from the request extensions
-            let claims = req.get::<Claims>();
-            let db_user_id: String = record.get::<String, _>("user_id"); // Extract user_id from the query result
-            if let Some(claims) = claims {
-                if db_user_id != claims.sub {
-                    return HttpResponse::Unauthorized().json("Unauthorized to update this URL");
-                    // User not authorized to update
-                }
+            let owner: Option<String> = record.get("user_id"); // Extract user_id from the query result
+            let resource = Resource::Url {
+                owner: owner.as_deref(),
+            };
+            if let Err(response) = authorize(&req, Action::Delete, resource) {
+                return response;
             }
         }
+        Err(sqlx::Error::RowNotFound) => return HttpResponse::NotFound().json("URL not found"),
         Err(err) => {
             eprintln!("Error fetching URL user_id: {}", err); // Log the error to the console
             return HttpResponse::InternalServerError().json("Internal Server Error");
@@ -172,6 +556,9 @@ pub async fn delete_url(
         }
     }
 
+    // Evict every code of the URL while its aliases can still be looked up
+    invalidate_cached_codes(db_pool.get_ref(), &cache, &url_id).await;
+
     // SQL query to delete the URL from the database
     let query = "DELETE FROM short_urls WHERE id = ?";
 
@@ -195,42 +582,48 @@ pub async fn delete_url(
 }
 
 /// Handle redirect from short URL to original URL.
-#[get("/s/{short_code}")]
+///
+/// `HEAD` answers with the same status and `Location` but does not count a click.
+#[route("/s/{short_code}", method = "GET", method = "HEAD")]
 pub async fn redirect_to_original(
+    req: HttpRequest,
     short_code: Path<String>,    // Extract short code from the URL
     db_pool: Data<DatabasePool>, // Inject the database pool
+    config: Data<AppConfig>,     // Expired-link behavior
+    clicks: Data<ClickCounter>,  // Background click counter
+    cache: Data<RedirectCache>,  // Cached lookups, including unknown codes
 ) -> impl Responder {
-    // Query the database for the short URL's corresponding original URL
-    let short_url = sqlx::query_as::<_, ShortUrl>(
-        r#"
-        SELECT * FROM short_urls WHERE short_code = ?
-        "#,
-    )
-    .bind(short_code.into_inner()) // Bind the short code to the query
-    .fetch_optional(&**db_pool) // Fetch the URL, or return None if not found
-    .await;
+    // Serve from the cache when possible, otherwise query the database and remember the answer
+    let short_url = match cache.get(&short_code) {
+        Some(cached) => Ok(cached),
+        None => {
+            let result = find_by_short_code(&db_pool, &short_code).await;
+            if let Ok(url) = &result {
+                cache.insert(short_code.to_string(), url.clone());
+            }
+            result
+        }
+    };
 
     match short_url {
         Ok(Some(url)) => {
-            // Increment the click_count for the short URL in the database
-            let update_result = sqlx::query(
-                r#"
-                UPDATE short_urls 
-                SET click_count = click_count + 1 
-                WHERE short_code = ?
-                "#,
-            )
-            .bind(&url.short_code) // Bind the short code to the query
-            .execute(&**db_pool)
-            .await;
+            // Expired links are not followed and their clicks are not counted
+            if let Some(response) = expired_link_response(&url, &config) {
+                return response;
+            }
 
-            if let Err(err) = update_result {
-                // Log or handle the error if updating click count fails
-                eprintln!("Failed to update click count: {:?}", err);
+            // Count the click in the background; it is written to the database in batches
+            if req.method() != Method::HEAD {
+                clicks.record(ClickEvent::from_request(
+                    &req,
+                    &url.id,
+                    &config.trusted_proxies,
+                ));
             }
 
-            // Redirect the user to the original URL
-            HttpResponse::Found()
+            // Redirect the user to the original URL with the link's status, or the server default
+            let redirect_type = url.redirect_type.unwrap_or(config.default_redirect_type);
+            HttpResponse::build(redirect_status(redirect_type))
                 .append_header(("Location", url.original_url))
                 .finish()
         }
@@ -245,6 +638,60 @@ pub async fn redirect_to_original(
     }
 }
 
+fn redirect_status(redirect_type: RedirectType) -> StatusCode {
+    match redirect_type {
+        RedirectType::MovedPermanently => StatusCode::MOVED_PERMANENTLY,
+        RedirectType::Found => StatusCode::FOUND,
+        RedirectType::TemporaryRedirect => StatusCode::TEMPORARY_REDIRECT,
+        RedirectType::PermanentRedirect => StatusCode::PERMANENT_REDIRECT,
+    }
+}
+
+/// Looks up a short code, falling back to codes that were replaced recently and
+/// are still within their grace period. A link's current code wins over an alias.
+async fn find_by_short_code(
+    db_pool: &DatabasePool,
+    short_code: &str,
+) -> Result<Option<ShortUrl>, sqlx::Error> {
+    sqlx::query_as::<_, ShortUrl>(
+        r#"
+        SELECT s.*, 0 AS precedence FROM short_urls s WHERE s.short_code = ?
+        UNION ALL
+        SELECT s.*, 1 AS precedence FROM short_urls s
+        JOIN short_url_aliases a ON a.short_url_id = s.id
+        WHERE a.short_code = ? AND a.expires_at > ?
+        ORDER BY precedence
+        LIMIT 1
+        "#,
+    )
+    .bind(short_code) // Bind the short code to the query
+    .bind(short_code)
+    .bind(Utc::now())
+    .fetch_optional(db_pool) // Fetch the URL, or return None if not found
+    .await
+}
+
+/// Builds the response for an expired link, or `None` if the link is still valid.
+///
+/// A per-link `expired_redirect_url` takes precedence over the server-wide behavior.
+fn expired_link_response(url: &ShortUrl, config: &AppConfig) -> Option<HttpResponse> {
+    if !url.is_expired() {
+        return None;
+    }
+
+    let response = match (&url.expired_redirect_url, config.expired_link_behavior) {
+        (Some(fallback_url), _) => HttpResponse::Found()
+            .append_header(("Location", fallback_url.as_str()))
+            .finish(),
+        (None, ExpiredLinkBehavior::Page) => HttpResponse::Gone()
+            .content_type("text/html; charset=utf-8")
+            .body(config.expired_link_page.clone()),
+        (None, ExpiredLinkBehavior::Gone) => HttpResponse::Gone().json("Short URL has expired"),
+    };
+
+    Some(response)
+}
+
 /// Get URL by ID
 #[get("/{url_id}")]
 pub async fn get_short_url_by_id(
@@ -252,13 +699,6 @@ pub async fn get_short_url_by_id(
     url_id: Path<String>,
     db_pool: Data<DatabasePool>,
 ) -> impl Responder {
-    // Extract the claims from the request's extensions
-    let req = req.extensions();
-    let claims = match req.get::<Claims>() {
-        Some(claims) => claims,
-        None => return HttpResponse::Unauthorized().body("Missing or invalid JWT claims"),
-    };
-
     let url_id = url_id.into_inner();
 
     // Query the database for the URL and its owner
@@ -268,16 +708,242 @@ pub async fn get_short_url_by_id(
         .await
     {
         Ok(url) => {
-            // Check if the URL has an owner and if the user has access to it
-            match &url.user_id {
-                Some(user_id) if *user_id == claims.sub || claims.roles.contains("admin") => {
-                    HttpResponse::Ok().json(url)
-                }
-                Some(_) => HttpResponse::Forbidden().body("You do not have access to this URL"), // Another user owns it
-                None => HttpResponse::Forbidden().body("This URL does not have an owner"), // URL exists but has no owner
+            // Check if the user has access to the URL (its owner or an admin)
+            let resource = Resource::Url {
+                owner: url.user_id.as_deref(),
+            };
+            match authorize(&req, Action::Read, resource) {
+                Ok(_) => HttpResponse::Ok().json(url),
+                Err(response) => response,
             }
         }
         Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().body("URL not found"),
         Err(e) => HttpResponse::InternalServerError().json(format!("Database error: {}", e)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+
+    fn url_expiring_in(offset: Option<Duration>) -> ShortUrl {
+        ShortUrl {
+            original_url: "https://example.com".to_string(),
+            short_code: "abc123".to_string(),
+            expiration: offset.map(|offset| Utc::now() + offset),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn link_without_expiration_is_followed() {
+        let url = url_expiring_in(None);
+        assert!(expired_link_response(&url, &AppConfig::default()).is_none());
+    }
+
+    #[test]
+    fn link_not_yet_expired_is_followed() {
+        let url = url_expiring_in(Some(Duration::hours(1)));
+        assert!(expired_link_response(&url, &AppConfig::default()).is_none());
+    }
+
+    #[test]
+    fn expired_link_returns_gone() {
+        let url = url_expiring_in(Some(Duration::hours(-1)));
+        let response = expired_link_response(&url, &AppConfig::default()).unwrap();
+        assert_eq!(response.status(), StatusCode::GONE);
+    }
+
+    #[actix_web::test]
+    async fn expired_link_serves_branded_page() {
+        let url = url_expiring_in(Some(Duration::hours(-1)));
+        let config = AppConfig {
+            expired_link_behavior: ExpiredLinkBehavior::Page,
+            ..Default::default()
+        };
+
+        let response = expired_link_response(&url, &config).unwrap();
+        assert_eq!(response.status(), StatusCode::GONE);
+        let body = to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, config.expired_link_page.as_bytes());
+    }
+
+    #[test]
+    fn expired_link_uses_fallback_url() {
+        let mut url = url_expiring_in(Some(Duration::hours(-1)));
+        url.expired_redirect_url = Some("https://example.com/expired".to_string());
+
+        let response = expired_link_response(&url, &AppConfig::default()).unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(
+            response.headers().get("Location").unwrap(),
+            "https://example.com/expired"
+        );
+    }
+
+    #[test]
+    fn empty_updates_are_detected() {
+        let parse = |body: &str| serde_json::from_str::<UpdateUrlRequest>(body).unwrap();
+
+        assert!(parse("{}").is_empty());
+        assert!(parse(r#"{"regenerate_code": false}"#).is_empty());
+        assert!(!parse(r#"{"regenerate_code": true}"#).is_empty());
+        assert!(!parse(r#"{"original_url": "https://example.com"}"#).is_empty());
+    }
+
+    fn chain_config(max_redirect_chain_depth: usize) -> AppConfig {
+        AppConfig {
+            public_hosts: vec!["sho.rt".to_string(), "localhost:8080".to_string()],
+            max_redirect_chain_depth,
+            ..AppConfig::default()
+        }
+    }
+
+    fn invalid<T>(result: Result<T, ChainError>) -> bool {
+        matches!(result, Err(ChainError::Invalid(_)))
+    }
+
+    /// Resolves `destination` against links given as `(id, code, destination)`.
+    async fn walk(
+        config: &AppConfig,
+        url_id: Option<&str>,
+        destination: &str,
+        links: &[(&str, &str, &str)],
+    ) -> Result<String, ChainError> {
+        walk_redirect_chain(config, url_id, destination.to_string(), |code| {
+            let link = links
+                .iter()
+                .find(|(_, short_code, _)| *short_code == code)
+                .map(|(id, _, url)| (id.to_string(), Some(url.to_string())));
+            async move { Ok(link) }
+        })
+        .await
+    }
+
+    #[test]
+    fn recognizes_own_short_links_by_host_and_port() {
+        let config = chain_config(1);
+        let code = |url: &str| own_short_code(url, &config).ok().flatten();
+
+        assert_eq!(code("https://sho.rt/s/abc").as_deref(), Some("abc"));
+        assert_eq!(code("https://SHO.RT/s/abc").as_deref(), Some("abc"));
+        assert_eq!(code("http://localhost:8080/s/abc").as_deref(), Some("abc"));
+        assert_eq!(code("https://example.com/s/abc"), None);
+        assert_eq!(code("http://localhost:9999/s/abc"), None);
+        assert_eq!(code("https://sho.rt:8443/s/abc"), None);
+        assert!(invalid(own_short_code("https://sho.rt/urls/1", &config)));
+        assert!(invalid(own_short_code("https://sho.rt/s/a/b", &config)));
+    }
+
+    #[actix_web::test]
+    async fn external_and_shallow_chains_are_kept() {
+        let config = chain_config(1);
+        let links = [("1", "one", "https://example.com")];
+
+        assert_eq!(
+            walk(&config, None, "https://example.com", &links)
+                .await
+                .ok()
+                .as_deref(),
+            Some("https://example.com")
+        );
+        assert_eq!(
+            walk(&config, None, "https://sho.rt/s/one", &links)
+                .await
+                .ok()
+                .as_deref(),
+            Some("https://sho.rt/s/one")
+        );
+        assert!(invalid(
+            walk(&config, None, "https://sho.rt/s/nope", &links).await
+        ));
+    }
+
+    #[actix_web::test]
+    async fn deep_chains_are_flattened() {
+        let config = chain_config(1);
+        let links = [
+            ("1", "one", "https://sho.rt/s/two"),
+            ("2", "two", "https://example.com"),
+        ];
+
+        assert_eq!(
+            walk(&config, None, "https://sho.rt/s/one", &links)
+                .await
+                .ok()
+                .as_deref(),
+            Some("https://example.com")
+        );
+    }
+
+    #[actix_web::test]
+    async fn loops_are_rejected() {
+        let config = chain_config(5);
+        let links = [
+            ("1", "one", "https://sho.rt/s/two"),
+            ("2", "two", "https://sho.rt/s/one"),
+            ("3", "three", "https://example.com"),
+        ];
+
+        assert!(invalid(
+            walk(&config, None, "https://sho.rt/s/one", &links).await
+        ));
+        // A link pointing back at itself through another link
+        assert!(invalid(
+            walk(&config, Some("3"), "https://sho.rt/s/three", &links).await
+        ));
+    }
+
+    #[actix_web::test]
+    async fn expired_links_are_followed_to_their_fallback() {
+        let config = chain_config(5);
+        let mut one = url_expiring_in(Some(Duration::hours(-1)));
+        one.short_code = "one".to_string();
+        one.original_url = "https://example.com".to_string();
+        one.expired_redirect_url = Some("https://sho.rt/s/two".to_string());
+        let mut two = url_expiring_in(None);
+        two.short_code = "two".to_string();
+        two.original_url = "https://sho.rt/s/one".to_string();
+        let mut dead = url_expiring_in(Some(Duration::hours(-1)));
+        dead.short_code = "dead".to_string();
+        dead.original_url = "https://sho.rt/s/one".to_string();
+        let links = [one, two, dead];
+        let walk = |destination: &str| {
+            walk_redirect_chain(&config, None, destination.to_string(), |code| {
+                let link = links
+                    .iter()
+                    .find(|url| url.short_code == code)
+                    .cloned()
+                    .map(chain_hop);
+                async move { Ok(link) }
+            })
+        };
+
+        // one has expired into two, which points back at one
+        assert!(invalid(walk("https://sho.rt/s/one").await));
+        // An expired link without a fallback ends the chain
+        assert_eq!(
+            walk("https://sho.rt/s/dead").await.ok().as_deref(),
+            Some("https://sho.rt/s/dead")
+        );
+    }
+
+    #[actix_web::test]
+    async fn overlong_chains_are_rejected() {
+        let config = chain_config(usize::MAX);
+        let ids: Vec<String> = (0..=MAX_CHAIN_HOPS + 1).map(|i| i.to_string()).collect();
+        let destinations: Vec<String> = (0..=MAX_CHAIN_HOPS + 1)
+            .map(|i| format!("https://sho.rt/s/{}", i + 1))
+            .collect();
+        let links: Vec<(&str, &str, &str)> = ids
+            .iter()
+            .zip(&destinations)
+            .map(|(id, destination)| (id.as_str(), id.as_str(), destination.as_str()))
+            .collect();
+
+        assert!(invalid(
+            walk(&config, None, "https://sho.rt/s/0", &links).await
+        ));
+    }
+}
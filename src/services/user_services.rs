@@ -1,29 +1,54 @@
-use actix_url_shortener::generate_password_hash;
+use actix_url_shortener::password::PasswordHasher;
 use actix_web::{
     delete, get, post, put,
     web::{self, Data, Json, Path},
-    HttpResponse, Responder,
+    HttpRequest, HttpResponse, Responder,
 };
 use serde_json::json;
 
 use crate::{
+    config::AppConfig,
     database::DatabasePool,
+    policy::{authorize, Action, Resource},
     schema::{
         url::ShortUrl,
         user::{CreateUserRequest, UpdateUserRequest, User},
     },
+    services::{
+        api_key_services::revoke_user_api_keys,
+        token_services::{invalidate_access_tokens, revoke_user_refresh_tokens},
+    },
 };
 
 /// Inserts a new user into the database.
 #[post("/")]
 pub async fn create_user(
+    http_req: HttpRequest,
     db_pool: web::Data<DatabasePool>,
+    config: web::Data<AppConfig>,
+    hasher: web::Data<dyn PasswordHasher>,
     req_body: web::Json<CreateUserRequest>,
 ) -> impl Responder {
+    if let Err(response) = authorize(&http_req, Action::Create, Resource::User) {
+        return response;
+    }
+
     // Extract request data
     let req = req_body.into_inner();
+    let errors = req.validate(&config.password_policy);
+    if !errors.is_empty() {
+        return HttpResponse::UnprocessableEntity().json(errors);
+    }
     let mut user = User::default();
-    user.set_password(&req.password).unwrap().set_roles();
+    match user.set_password(hasher.get_ref(), &req.password) {
+        Ok(user) => user.set_roles(),
+        Err(e) => {
+            eprintln!("Failed to hash password: {}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "error": "Failed to create user"
+            }));
+        }
+    };
     let roles_as_json = serde_json::to_string(&user.roles).unwrap();
 
     // Insert user into the database
@@ -36,8 +61,8 @@ pub async fn create_user(
         .bind(req.username)
         .bind(req.email)
         .bind(&user.password)
-        .bind(&user.created_at)
-        .bind(&user.updated_at)
+        .bind(user.created_at)
+        .bind(user.updated_at)
         .bind(true)
         .bind(roles_as_json)
         .execute(db_pool.get_ref())
@@ -65,7 +90,10 @@ pub async fn create_user(
 }
 
 #[get("/")]
-pub async fn list_users(db_pool: web::Data<DatabasePool>) -> impl Responder {
+pub async fn list_users(req: HttpRequest, db_pool: web::Data<DatabasePool>) -> impl Responder {
+    if let Err(response) = authorize(&req, Action::Read, Resource::User) {
+        return response;
+    }
     println!("run the function");
     match sqlx::query_as::<_, User>("SELECT * FROM users")
         .fetch_all(db_pool.get_ref())
@@ -80,7 +108,14 @@ pub async fn list_users(db_pool: web::Data<DatabasePool>) -> impl Responder {
 }
 
 #[get("/{user_id}/urls")]
-pub async fn list_user_urls(path: Path<String>, db: Data<DatabasePool>) -> impl Responder {
+pub async fn list_user_urls(
+    req: HttpRequest,
+    path: Path<String>,
+    db: Data<DatabasePool>,
+) -> impl Responder {
+    if let Err(response) = authorize(&req, Action::Read, Resource::User) {
+        return response;
+    }
     let user_id = path.into_inner();
     match sqlx::query_as::<_, ShortUrl>(
         r#"
@@ -103,7 +138,14 @@ pub async fn list_user_urls(path: Path<String>, db: Data<DatabasePool>) -> impl
 }
 
 #[delete("/{user_id}")]
-pub async fn delete_user_by_id(user_id: Path<String>, db: Data<DatabasePool>) -> impl Responder {
+pub async fn delete_user_by_id(
+    req: HttpRequest,
+    user_id: Path<String>,
+    db: Data<DatabasePool>,
+) -> impl Responder {
+    if let Err(response) = authorize(&req, Action::Delete, Resource::User) {
+        return response;
+    }
     match sqlx::query("DELETE FROM users WHERE id = ? ")
         .bind(user_id.into_inner())
         .execute(db.as_ref())
@@ -127,7 +169,14 @@ pub async fn delete_user_by_id(user_id: Path<String>, db: Data<DatabasePool>) ->
 }
 
 #[get("/{user_id}")]
-pub async fn get_user_by_id(user_id: Path<String>, db: Data<DatabasePool>) -> impl Responder {
+pub async fn get_user_by_id(
+    req: HttpRequest,
+    user_id: Path<String>,
+    db: Data<DatabasePool>,
+) -> impl Responder {
+    if let Err(response) = authorize(&req, Action::Read, Resource::User) {
+        return response;
+    }
     match sqlx::query_as::<_, User>("SELECT * FROM user WHERE id = ?")
         .bind(user_id.into_inner())
         .fetch_one(db.as_ref())
@@ -143,15 +192,45 @@ pub async fn get_user_by_id(user_id: Path<String>, db: Data<DatabasePool>) -> im
 
 #[put("/{user_id}")]
 pub async fn update_user_by_id(
+    req: HttpRequest,
     user_id: Path<String>,
     updated_user: Json<UpdateUserRequest>,
     db: Data<DatabasePool>,
+    config: Data<AppConfig>,
+    hasher: Data<dyn PasswordHasher>,
 ) -> impl Responder {
+    if let Err(response) = authorize(&req, Action::Update, Resource::User) {
+        return response;
+    }
+
     let user_id = user_id.into_inner();
     let updated_user = updated_user.into_inner();
 
+    // A new password is checked against the username, which may be the current one
+    let current_username = if updated_user.password.is_some() && updated_user.username.is_none() {
+        match sqlx::query_scalar::<_, String>("SELECT username FROM users WHERE id = ?")
+            .bind(&user_id)
+            .fetch_optional(db.as_ref())
+            .await
+        {
+            Ok(Some(username)) => username,
+            Ok(None) => return HttpResponse::NotFound().json("User not found"),
+            Err(err) => {
+                eprintln!("Error fetching user: {}", err);
+                return HttpResponse::InternalServerError().json("Failed to update user");
+            }
+        }
+    } else {
+        String::new()
+    };
+    let errors = updated_user.validate(&config.password_policy, &current_username);
+    if !errors.is_empty() {
+        return HttpResponse::UnprocessableEntity().json(errors);
+    }
+
     let mut query = String::from("UPDATE users SET ");
     let mut params = vec![];
+    let password_changed = updated_user.password.is_some();
 
     if let Some(username) = updated_user.username {
         query.push_str("username = ?, ");
@@ -163,7 +242,13 @@ pub async fn update_user_by_id(
     }
     if let Some(password) = updated_user.password {
         query.push_str("password = ?, ");
-        let hashed_password = generate_password_hash(&password).unwrap();
+        let hashed_password = match hasher.hash(&password) {
+            Ok(hashed_password) => hashed_password,
+            Err(err) => {
+                eprintln!("Error hashing password: {}", err);
+                return HttpResponse::InternalServerError().json("Failed to update user");
+            }
+        };
         params.push(hashed_password);
     }
 
@@ -175,21 +260,31 @@ pub async fn update_user_by_id(
 
     // Add the WHERE clause to target the correct user by ID
     query.push_str(" WHERE id = ?");
-    params.push(user_id);
-
-    let mut query_builder = sqlx::query(&query);
-    for param in params {
-        query_builder = query_builder.bind(param);
-    }
+    params.push(user_id.clone());
 
-    match query_builder.execute(db.as_ref()).await {
-        Ok(result) => {
-            if result.rows_affected() > 0 {
-                HttpResponse::Ok().json("User updated successfully")
-            } else {
-                HttpResponse::NotFound().json("User not found")
-            }
+    // The new password and the end of every session issued under the old one are
+    // committed together, or not at all
+    let updated = async {
+        let mut tx = db.begin().await?;
+        let mut query_builder = sqlx::query(&query);
+        for param in params {
+            query_builder = query_builder.bind(param);
+        }
+        if query_builder.execute(&mut *tx).await?.rows_affected() == 0 {
+            return Ok(false);
         }
+        if password_changed {
+            invalidate_access_tokens(&mut *tx, &user_id).await?;
+            revoke_user_refresh_tokens(&mut *tx, &user_id).await?;
+            revoke_user_api_keys(&mut *tx, &user_id).await?;
+        }
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(true)
+    };
+
+    match updated.await {
+        Ok(true) => HttpResponse::Ok().json("User updated successfully"),
+        Ok(false) => HttpResponse::NotFound().json("User not found"),
         Err(err) => {
             eprintln!("Error updating user: {}", err);
             HttpResponse::InternalServerError().json("Failed to update user")
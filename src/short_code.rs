@@ -0,0 +1,241 @@
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+use crate::generate_short_code_from_url;
+
+/// Characters used by the base62 strategies.
+const BASE62_ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Encodes a number in base62, left-padded with `0` up to `min_length` characters.
+pub fn encode_base62(mut value: u64, min_length: usize) -> String {
+    let mut digits = Vec::new();
+    while value > 0 {
+        digits.push(BASE62_ALPHABET[(value % 62) as usize]);
+        value /= 62;
+    }
+    while digits.len() < min_length.max(1) {
+        digits.push(b'0');
+    }
+    digits.reverse();
+    String::from_utf8(digits).expect("base62 alphabet is ASCII")
+}
+
+/// Produces candidate short codes for new links.
+///
+/// The caller inserts the candidate and, if it hits the unique index on
+/// `short_urls.short_code`, asks again with `attempt + 1` until a bound is reached.
+pub trait ShortCodeGenerator: Send + Sync {
+    /// Returns a candidate code for `original_url`. `attempt` starts at 0.
+    fn generate(&self, original_url: &str, attempt: u32) -> String;
+}
+
+/// Random base62 codes of a fixed length.
+pub struct RandomGenerator {
+    length: usize,
+}
+
+impl ShortCodeGenerator for RandomGenerator {
+    fn generate(&self, _original_url: &str, _attempt: u32) -> String {
+        let mut rng = rand::thread_rng();
+        (0..self.length)
+            .map(|_| BASE62_ALPHABET[rng.gen_range(0..BASE62_ALPHABET.len())] as char)
+            .collect()
+    }
+}
+
+/// Base62-encoded, increasing IDs.
+///
+/// The counter lives in memory and is seeded from the startup time in milliseconds;
+/// it is not persisted. Codes only increase within one process: after a restart
+/// within fewer milliseconds than codes were handed out, or with several instances
+/// running, values repeat. The unique index catches those, and a collision simply
+/// moves on to the next value.
+pub struct CounterGenerator {
+    next: AtomicU64,
+    length: usize,
+}
+
+impl CounterGenerator {
+    pub fn new(start: u64, length: usize) -> Self {
+        Self {
+            next: AtomicU64::new(start),
+            length,
+        }
+    }
+}
+
+impl ShortCodeGenerator for CounterGenerator {
+    fn generate(&self, _original_url: &str, _attempt: u32) -> String {
+        encode_base62(self.next.fetch_add(1, Ordering::Relaxed), self.length)
+    }
+}
+
+/// SHA-256 of a secret salt, the URL and the attempt number, in base62.
+///
+/// Unlike [`HashGenerator`], the same URL yields different codes on different
+/// deployments, and the attempt number resolves collisions.
+pub struct SaltedHashGenerator {
+    salt: String,
+    length: usize,
+}
+
+impl ShortCodeGenerator for SaltedHashGenerator {
+    fn generate(&self, original_url: &str, attempt: u32) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.salt);
+        hasher.update(original_url);
+        hasher.update(attempt.to_be_bytes());
+        let digest = hasher.finalize();
+
+        // One base62 character per digest byte; SHA-256 yields 32 bytes
+        digest
+            .iter()
+            .take(self.length)
+            .map(|byte| BASE62_ALPHABET[*byte as usize % BASE62_ALPHABET.len()] as char)
+            .collect()
+    }
+}
+
+/// The original behavior: the first `length` hex characters of SHA-256(url).
+///
+/// Retries hash the URL with the attempt number appended, so the first attempt
+/// still matches codes issued before strategies existed.
+pub struct HashGenerator {
+    length: usize,
+}
+
+impl ShortCodeGenerator for HashGenerator {
+    fn generate(&self, original_url: &str, attempt: u32) -> String {
+        if attempt == 0 {
+            generate_short_code_from_url(original_url, self.length)
+        } else {
+            generate_short_code_from_url(&format!("{}#{}", original_url, attempt), self.length)
+        }
+    }
+}
+
+/// Selects a [`ShortCodeGenerator`] implementation by name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShortCodeStrategy {
+    Random,
+    Counter,
+    SaltedHash,
+    Hash,
+}
+
+impl FromStr for ShortCodeStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "random" => Ok(Self::Random),
+            "counter" => Ok(Self::Counter),
+            "salted_hash" => Ok(Self::SaltedHash),
+            "hash" => Ok(Self::Hash),
+            other => Err(format!("Unknown short code strategy: {}", other)),
+        }
+    }
+}
+
+impl ShortCodeStrategy {
+    /// Builds the generator for this strategy. `salt` is only used by `SaltedHash`.
+    pub fn build(self, length: usize, salt: &str) -> Arc<dyn ShortCodeGenerator> {
+        match self {
+            Self::Random => Arc::new(RandomGenerator { length }),
+            Self::Counter => {
+                let start = chrono::Utc::now().timestamp_millis() as u64;
+                Arc::new(CounterGenerator::new(start, length))
+            }
+            Self::SaltedHash => Arc::new(SaltedHashGenerator {
+                salt: salt.to_string(),
+                length,
+            }),
+            Self::Hash => Arc::new(HashGenerator { length }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn is_base62(code: &str) -> bool {
+        code.bytes().all(|byte| BASE62_ALPHABET.contains(&byte))
+    }
+
+    #[test]
+    fn encodes_base62_with_padding() {
+        assert_eq!(encode_base62(0, 0), "0");
+        assert_eq!(encode_base62(61, 1), "z");
+        assert_eq!(encode_base62(62, 1), "10");
+        assert_eq!(encode_base62(62, 4), "0010");
+        assert_eq!(encode_base62(u64::MAX, 1), "LygHa16AHYF");
+    }
+
+    #[test]
+    fn generators_respect_length_and_alphabet() {
+        for strategy in [
+            ShortCodeStrategy::Random,
+            ShortCodeStrategy::Counter,
+            ShortCodeStrategy::SaltedHash,
+        ] {
+            let generator = strategy.build(12, "salt");
+            let code = generator.generate("https://example.com", 0);
+            assert_eq!(code.len(), 12, "{:?}", strategy);
+            assert!(is_base62(&code), "{:?}", strategy);
+        }
+
+        let code = ShortCodeStrategy::Hash
+            .build(10, "")
+            .generate("https://example.com", 0);
+        assert_eq!(code.len(), 10);
+        assert!(code.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+
+    #[test]
+    fn salted_hash_is_deterministic_per_salt_and_attempt() {
+        let generator = ShortCodeStrategy::SaltedHash.build(10, "secret");
+        let code = generator.generate("https://example.com", 0);
+
+        assert_eq!(generator.generate("https://example.com", 0), code);
+        assert_ne!(generator.generate("https://example.com", 1), code);
+        assert_ne!(generator.generate("https://example.org", 0), code);
+        let other_salt = ShortCodeStrategy::SaltedHash.build(10, "other");
+        assert_ne!(other_salt.generate("https://example.com", 0), code);
+    }
+
+    #[test]
+    fn hash_generator_keeps_the_original_first_code() {
+        let generator = ShortCodeStrategy::Hash.build(10, "");
+        assert_eq!(
+            generator.generate("https://example.com", 0),
+            generate_short_code_from_url("https://example.com", 10)
+        );
+        assert_ne!(
+            generator.generate("https://example.com", 1),
+            generator.generate("https://example.com", 0)
+        );
+    }
+
+    #[test]
+    fn counter_codes_are_unique_and_increasing() {
+        let generator = CounterGenerator::new(1000, 6);
+        let codes: Vec<String> = (0..500)
+            .map(|_| generator.generate("https://example.com", 0))
+            .collect();
+
+        assert_eq!(codes.iter().collect::<HashSet<_>>().len(), codes.len());
+        // Same length and an ASCII-ordered alphabet, so string order is numeric order
+        assert!(codes.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
@@ -0,0 +1,369 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::prelude::FromRow;
+
+use crate::database::DatabasePool;
+
+/// Sign-in brute-force protection settings.
+///
+/// Failures are counted per email and per client IP over a sliding `window`. After
+/// `delay_after` failures for an email each further attempt is slowed down, and
+/// reaching a lockout threshold blocks sign-in for `lockout_duration`.
+#[derive(Debug, Clone)]
+pub struct LoginThrottle {
+    pub window: Duration,
+    pub delay_after: u32,
+    /// First delay; doubled with each further failure up to `max_delay`.
+    pub base_delay: std::time::Duration,
+    pub max_delay: std::time::Duration,
+    pub email_lockout_threshold: u32,
+    pub ip_lockout_threshold: u32,
+    pub lockout_duration: Duration,
+}
+
+impl Default for LoginThrottle {
+    fn default() -> Self {
+        Self {
+            window: Duration::minutes(15),
+            delay_after: 3,
+            base_delay: std::time::Duration::from_millis(500),
+            max_delay: std::time::Duration::from_secs(8),
+            email_lockout_threshold: 10,
+            ip_lockout_threshold: 50,
+            lockout_duration: Duration::minutes(15),
+        }
+    }
+}
+
+impl LoginThrottle {
+    /// How long to hold back an attempt for an email with `failures` recent failures.
+    pub fn delay(&self, failures: u32) -> std::time::Duration {
+        if failures < self.delay_after {
+            return std::time::Duration::ZERO;
+        }
+        let doublings = (failures - self.delay_after).min(16);
+        self.base_delay
+            .saturating_mul(2u32.pow(doublings))
+            .min(self.max_delay)
+    }
+}
+
+/// What a lockout applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockoutScope {
+    Email,
+    Ip,
+}
+
+impl LockoutScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LockoutScope::Email => "email",
+            LockoutScope::Ip => "ip",
+        }
+    }
+}
+
+/// An active sign-in lockout, as shown to admins.
+#[derive(Debug, Clone, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Lockout {
+    pub id: u64,
+    pub scope: String,
+    pub subject: String,
+    pub failures: u32,
+    pub locked_at: DateTime<Utc>,
+    pub locked_until: DateTime<Utc>,
+}
+
+/// Emails are compared case-insensitively, so `Alice@` and `alice@` share a counter.
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// Returns when the latest lockout covering this email or IP ends, if any is active.
+pub async fn active_lockout(
+    db_pool: &DatabasePool,
+    email: &str,
+    ip: Option<&str>,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    sqlx::query_scalar::<_, Option<DateTime<Utc>>>(
+        r#"
+        SELECT MAX(locked_until)
+        FROM login_lockouts
+        WHERE locked_until > ? AND ((scope = ? AND subject = ?) OR (scope = ? AND subject = ?))
+        "#,
+    )
+    .bind(Utc::now())
+    .bind(LockoutScope::Email.as_str())
+    .bind(email)
+    .bind(LockoutScope::Ip.as_str())
+    .bind(ip.unwrap_or_default())
+    .fetch_one(db_pool)
+    .await
+}
+
+/// Number of failures for an email inside the window.
+pub async fn recent_failures(
+    db_pool: &DatabasePool,
+    throttle: &LoginThrottle,
+    email: &str,
+) -> Result<u32, sqlx::Error> {
+    let failures = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM login_failures WHERE email = ? AND failed_at > ?",
+    )
+    .bind(email)
+    .bind(Utc::now() - throttle.window)
+    .fetch_one(db_pool)
+    .await?;
+
+    Ok(failures as u32)
+}
+
+/// A sign-in attempt in progress.
+///
+/// The attempt is recorded as a failure before the credentials are checked, so
+/// parallel attempts cannot all pass the lockout check before any of them fails; it is
+/// taken back when the credentials turn out to be right.
+#[derive(Debug)]
+pub struct Attempt {
+    id: u64,
+    email: String,
+    ip: Option<String>,
+    /// Failures of the email in the window before this attempt.
+    pub prior_failures: u32,
+}
+
+/// Whether an attempt may go ahead.
+#[derive(Debug)]
+pub enum AttemptStart {
+    Allowed(Attempt),
+    /// The email or IP is locked out until then.
+    Locked(DateTime<Utc>),
+}
+
+/// Starts a sign-in attempt for an email from an IP, unless one of them is locked out.
+///
+/// Attempts that raced past the lockout check and would go over a threshold are
+/// refused here, and lock the email or IP themselves.
+pub async fn begin_attempt(
+    db_pool: &DatabasePool,
+    throttle: &LoginThrottle,
+    email: &str,
+    ip: Option<&str>,
+) -> Result<AttemptStart, sqlx::Error> {
+    if let Some(locked_until) = active_lockout(db_pool, email, ip).await? {
+        return Ok(AttemptStart::Locked(locked_until));
+    }
+
+    let now = Utc::now();
+    sqlx::query("DELETE FROM login_failures WHERE failed_at <= ?")
+        .bind(now - throttle.window)
+        .execute(db_pool)
+        .await?;
+    let id =
+        sqlx::query("INSERT INTO login_failures (email, ip_address, failed_at) VALUES (?, ?, ?)")
+            .bind(email)
+            .bind(ip)
+            .bind(now)
+            .execute(db_pool)
+            .await?
+            .last_insert_id();
+
+    let email_failures = recent_failures(db_pool, throttle, email).await?;
+    if email_failures > throttle.email_lockout_threshold {
+        let locked_until = lock(
+            db_pool,
+            throttle,
+            LockoutScope::Email,
+            email,
+            email_failures,
+        )
+        .await?;
+        return Ok(AttemptStart::Locked(locked_until));
+    }
+    if let Some(ip) = ip {
+        let ip_failures = recent_ip_failures(db_pool, throttle, ip).await?;
+        if ip_failures > throttle.ip_lockout_threshold {
+            let locked_until = lock(db_pool, throttle, LockoutScope::Ip, ip, ip_failures).await?;
+            return Ok(AttemptStart::Locked(locked_until));
+        }
+    }
+
+    Ok(AttemptStart::Allowed(Attempt {
+        id,
+        email: email.to_string(),
+        ip: ip.map(str::to_string),
+        prior_failures: email_failures.saturating_sub(1),
+    }))
+}
+
+impl Attempt {
+    /// Keeps the attempt as a failure and locks the email or IP once it reaches its
+    /// threshold.
+    pub async fn fail(
+        self,
+        db_pool: &DatabasePool,
+        throttle: &LoginThrottle,
+    ) -> Result<(), sqlx::Error> {
+        let email_failures = recent_failures(db_pool, throttle, &self.email).await?;
+        if email_failures >= throttle.email_lockout_threshold {
+            lock(
+                db_pool,
+                throttle,
+                LockoutScope::Email,
+                &self.email,
+                email_failures,
+            )
+            .await?;
+        }
+
+        if let Some(ip) = &self.ip {
+            let ip_failures = recent_ip_failures(db_pool, throttle, ip).await?;
+            if ip_failures >= throttle.ip_lockout_threshold {
+                lock(db_pool, throttle, LockoutScope::Ip, ip, ip_failures).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Takes the attempt back when the credentials were right but signing in goes on
+    /// elsewhere, e.g. with a second factor. Earlier failures still count.
+    pub async fn release(self, db_pool: &DatabasePool) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM login_failures WHERE id = ?")
+            .bind(self.id)
+            .execute(db_pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Forgets this attempt and every earlier failure of the email after signing in.
+    pub async fn succeed(self, db_pool: &DatabasePool) -> Result<(), sqlx::Error> {
+        clear_failures(db_pool, &self.email).await
+    }
+}
+
+/// Number of failures from an IP inside the window.
+async fn recent_ip_failures(
+    db_pool: &DatabasePool,
+    throttle: &LoginThrottle,
+    ip: &str,
+) -> Result<u32, sqlx::Error> {
+    let failures = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM login_failures WHERE ip_address = ? AND failed_at > ?",
+    )
+    .bind(ip)
+    .bind(Utc::now() - throttle.window)
+    .fetch_one(db_pool)
+    .await?;
+
+    Ok(failures as u32)
+}
+
+/// Forgets the failures of an email after a successful sign-in.
+pub async fn clear_failures(db_pool: &DatabasePool, email: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM login_failures WHERE email = ?")
+        .bind(email)
+        .execute(db_pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Lists the lockouts that are still in effect.
+pub async fn list_lockouts(db_pool: &DatabasePool) -> Result<Vec<Lockout>, sqlx::Error> {
+    sqlx::query_as::<_, Lockout>(
+        r#"
+        SELECT id, scope, subject, failures, locked_at, locked_until
+        FROM login_lockouts
+        WHERE locked_until > ?
+        ORDER BY locked_until DESC
+        "#,
+    )
+    .bind(Utc::now())
+    .fetch_all(db_pool)
+    .await
+}
+
+/// Lifts a lockout along with the failures that caused it. Returns `false` if it does not exist.
+pub async fn clear_lockout(db_pool: &DatabasePool, id: u64) -> Result<bool, sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+    let Some((scope, subject)) = sqlx::query_as::<_, (String, String)>(
+        "SELECT scope, subject FROM login_lockouts WHERE id = ? FOR UPDATE",
+    )
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Ok(false);
+    };
+
+    let failures = if scope == LockoutScope::Ip.as_str() {
+        "DELETE FROM login_failures WHERE ip_address = ?"
+    } else {
+        "DELETE FROM login_failures WHERE email = ?"
+    };
+    sqlx::query(failures)
+        .bind(&subject)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM login_lockouts WHERE id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Locks the email or IP out, returning until when.
+async fn lock(
+    db_pool: &DatabasePool,
+    throttle: &LoginThrottle,
+    scope: LockoutScope,
+    subject: &str,
+    failures: u32,
+) -> Result<DateTime<Utc>, sqlx::Error> {
+    let now = Utc::now();
+    let locked_until = now + throttle.lockout_duration;
+    sqlx::query(
+        r#"
+        INSERT INTO login_lockouts (scope, subject, failures, locked_at, locked_until)
+        VALUES (?, ?, ?, ?, ?)
+        ON DUPLICATE KEY UPDATE
+            failures = VALUES(failures),
+            locked_at = VALUES(locked_at),
+            locked_until = VALUES(locked_until)
+        "#,
+    )
+    .bind(scope.as_str())
+    .bind(subject)
+    .bind(failures)
+    .bind(now)
+    .bind(locked_until)
+    .execute(db_pool)
+    .await?;
+
+    Ok(locked_until)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_starts_after_threshold_and_doubles_up_to_max() {
+        let throttle = LoginThrottle::default();
+        let ms = |failures| throttle.delay(failures).as_millis();
+
+        assert_eq!(ms(0), 0);
+        assert_eq!(ms(2), 0);
+        assert_eq!(ms(3), 500);
+        assert_eq!(ms(4), 1000);
+        assert_eq!(ms(6), 4000);
+        assert_eq!(ms(7), 8000);
+        assert_eq!(ms(1000), 8000);
+    }
+}